@@ -0,0 +1,294 @@
+/*!
+Random mazes for the simulator.
+
+Every floor is a random maze with a few extra openings so that it has loops,
+and floors are joined by ramps. Each floor is laid out to the right of the one
+below it, so that a ramp leads to the tile straight ahead of it on the next
+floor, which is what [`Field::parse`] expects.
+*/
+#![allow(dead_code)]
+use crate::map::{Direction, Kind, Position, VictimKind, DIRECTIONS};
+use crate::sim::{Field, Tile};
+use anyhow::{bail, Result};
+use std::collections::{HashMap, HashSet};
+
+/// Largest floor side the generator builds, in tiles.
+pub const MAX_SIDE: u32 = 12;
+pub const MAX_FLOORS: u32 = 3;
+
+const VICTIMS: [VictimKind; 6] = [
+    VictimKind::H,
+    VictimKind::S,
+    VictimKind::U,
+    VictimKind::Red,
+    VictimKind::Yellow,
+    VictimKind::Green,
+];
+
+/// Size of the field and how often each kind of tile shows up. Densities are
+/// the chance of each tile (or each wall, for victims and loops) getting it.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Settings {
+    pub width: u32,
+    pub height: u32,
+    pub floors: u32,
+    /// Ramps between each floor and the next.
+    pub ramps: u32,
+    pub black: f32,
+    pub blue: f32,
+    pub checkpoints: f32,
+    pub victims: f32,
+    /// Chance of a wall inside a floor being taken out after the maze is
+    /// built, which makes more than one way between tiles.
+    pub loops: f32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            width: 6,
+            height: 5,
+            floors: 2,
+            ramps: 1,
+            black: 0.05,
+            blue: 0.05,
+            checkpoints: 0.05,
+            victims: 0.05,
+            loops: 0.1,
+        }
+    }
+}
+
+/// xorshift64*, so that a seed always gives the same maze.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Rng(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    fn chance(&mut self, p: f32) -> bool {
+        ((self.next() >> 40) as f32) < p * (1u32 << 24) as f32
+    }
+}
+
+fn link(tiles: &mut HashMap<Position, Tile>, pos: Position, direction: Direction, next: Position) {
+    tiles.get_mut(&pos).unwrap().links.insert(direction, next);
+    tiles
+        .get_mut(&next)
+        .unwrap()
+        .links
+        .insert(direction.back(), pos);
+}
+
+/// Tiles that can be reached from `start` without crossing a black tile.
+fn reachable(tiles: &HashMap<Position, Tile>, start: Position) -> usize {
+    let mut seen = HashSet::from([start]);
+    let mut queue = vec![start];
+    while let Some(pos) = queue.pop() {
+        for next in tiles[&pos].links.values() {
+            if tiles[next].kind != Kind::Black && seen.insert(*next) {
+                queue.push(*next);
+            }
+        }
+    }
+    seen.len()
+}
+
+/// Builds a random field from `settings`. The same seed always gives the
+/// same field.
+pub fn generate(settings: &Settings, seed: u64) -> Result<Field> {
+    let Settings {
+        width,
+        height,
+        floors,
+        ramps,
+        ..
+    } = *settings;
+    if !(1..=MAX_SIDE).contains(&width) || !(1..=MAX_SIDE).contains(&height) {
+        bail!("A floor has to be between 1x1 and {0}x{0} tiles", MAX_SIDE);
+    }
+    if !(1..=MAX_FLOORS).contains(&floors) {
+        bail!("There can be between 1 and {} floors", MAX_FLOORS);
+    }
+    if floors > 1 && !(1..=height).contains(&ramps) {
+        bail!("There has to be between 1 and {} ramps", height);
+    }
+    let densities = [
+        settings.black,
+        settings.blue,
+        settings.checkpoints,
+        settings.victims,
+        settings.loops,
+    ];
+    if densities
+        .iter()
+        .any(|density| !(0.0..=1.0).contains(density))
+    {
+        bail!("Densities have to be between 0 and 1");
+    }
+
+    let mut rng = Rng::new(seed);
+    let mut tiles: HashMap<Position, Tile> = HashMap::new();
+    let (width, height) = (width as i32, height as i32);
+    // Floor `level` starts at this column, one past the ramps of the floor
+    // below it.
+    let column = |level: i32| level * (width + 1);
+
+    for level in 0..floors as i32 {
+        let floor: Vec<Position> = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (column(level) + x, y, level)))
+            .collect();
+        for pos in &floor {
+            tiles.insert(*pos, Tile::new(Kind::Empty));
+        }
+
+        // Depth first maze, so every tile of the floor is connected.
+        let first = floor[rng.below(floor.len())];
+        let mut seen = HashSet::from([first]);
+        let mut stack = vec![first];
+        while let Some(pos) = stack.last().copied() {
+            let unseen: Vec<(Direction, Position)> = DIRECTIONS
+                .into_iter()
+                .map(|direction| (direction, direction.step(pos)))
+                .filter(|(_, next)| tiles.contains_key(next) && !seen.contains(next))
+                .collect();
+            if unseen.is_empty() {
+                stack.pop();
+                continue;
+            }
+            let (direction, next) = unseen[rng.below(unseen.len())];
+            link(&mut tiles, pos, direction, next);
+            seen.insert(next);
+            stack.push(next);
+        }
+
+        for pos in &floor {
+            for direction in [Direction::Right, Direction::Down] {
+                let next = direction.step(*pos);
+                if tiles.contains_key(&next)
+                    && !tiles[pos].links.contains_key(&direction)
+                    && rng.chance(settings.loops)
+                {
+                    link(&mut tiles, *pos, direction, next);
+                }
+            }
+        }
+    }
+
+    // Ramps go up from the last column of a floor to the first column of
+    // the next one, on different rows.
+    let mut special = HashSet::new();
+    for level in 0..floors as i32 - 1 {
+        let mut rows: Vec<i32> = (0..height).collect();
+        for _ in 0..ramps {
+            let y = rows.remove(rng.below(rows.len()));
+            let ramp = (column(level) + width, y, level);
+            let entry = (ramp.0 - 1, y, level);
+            let exit = (ramp.0 + 1, y, level + 1);
+            tiles.insert(ramp, Tile::new(Kind::Ramp));
+            link(&mut tiles, entry, Direction::Right, ramp);
+            link(&mut tiles, ramp, Direction::Right, exit);
+            special.extend([ramp, entry, exit]);
+        }
+    }
+
+    // Sorted, since the order of a HashMap changes from run to run.
+    let mut positions: Vec<Position> = tiles.keys().copied().collect();
+    positions.sort();
+    let first_floor: Vec<Position> = positions
+        .iter()
+        .filter(|pos| pos.2 == 0 && tiles[*pos].kind == Kind::Empty)
+        .copied()
+        .collect();
+    let start = first_floor[rng.below(first_floor.len())];
+    tiles.get_mut(&start).unwrap().kind = Kind::Start;
+    special.insert(start);
+
+    // A black tile is only kept if every other tile can still be reached
+    // from the start.
+    let mut open = tiles.len();
+    for pos in &positions {
+        if special.contains(pos) || !rng.chance(settings.black) {
+            continue;
+        }
+        tiles.get_mut(pos).unwrap().kind = Kind::Black;
+        if reachable(&tiles, start) == open - 1 {
+            open -= 1;
+        } else {
+            tiles.get_mut(pos).unwrap().kind = Kind::Empty;
+        }
+    }
+    for pos in &positions {
+        let tile = tiles.get_mut(pos).unwrap();
+        if tile.kind != Kind::Empty || special.contains(pos) {
+            continue;
+        }
+        if rng.chance(settings.checkpoints) {
+            tile.kind = Kind::Checkpoint;
+        } else if rng.chance(settings.blue) {
+            tile.kind = Kind::Blue;
+        }
+    }
+
+    // A victim on a wall between two tiles is read as facing the tile below
+    // or to the right of it, so that is the only side one is put on.
+    for pos in &positions {
+        if matches!(tiles[pos].kind, Kind::Black | Kind::Ramp) {
+            continue;
+        }
+        for direction in DIRECTIONS {
+            let next = direction.step(*pos);
+            let between = tiles.contains_key(&next);
+            if tiles[pos].links.contains_key(&direction)
+                || (between && matches!(direction, Direction::Down | Direction::Right))
+                || !rng.chance(settings.victims)
+            {
+                continue;
+            }
+            let kind = VICTIMS[rng.below(VICTIMS.len())];
+            tiles.get_mut(pos).unwrap().victims.insert(direction, kind);
+        }
+    }
+
+    Ok(Field { tiles, start })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_tile_can_be_reached() {
+        let dense = Settings {
+            floors: MAX_FLOORS,
+            ramps: 2,
+            black: 0.5,
+            ..Settings::default()
+        };
+        for settings in [Settings::default(), dense] {
+            for seed in 0..100 {
+                let field = generate(&settings, seed).unwrap();
+                let standing: HashSet<Position> = field
+                    .tiles
+                    .iter()
+                    .filter(|(_, tile)| !matches!(tile.kind, Kind::Black | Kind::Ramp))
+                    .map(|(pos, _)| *pos)
+                    .collect();
+                assert_eq!(field.reachable(), standing, "seed {}", seed);
+                assert!(Field::parse(&field.render()).is_ok(), "seed {}", seed);
+            }
+        }
+    }
+}
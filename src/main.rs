@@ -1,16 +1,94 @@
+mod generator;
 mod map;
+mod scoring;
 mod sensors;
+mod sim;
 mod vision;
 use std::thread;
 
 use crate::map::Maze;
 use crate::sensors::mpu6050::MPU6050;
 use crate::sensors::vl6180x::VL6180X;
+use crate::sim::{Field, Simulator};
 use crate::vision::{Detection, Vision};
 use rppal::gpio::Gpio;
 use std::sync::mpsc::channel;
 
+// Takes `name <value>` out of the arguments.
+fn take_option(args: &mut Vec<String>, name: &str) -> Option<String> {
+    let index = args.iter().position(|arg| arg == name)?;
+    let value = (index + 1 < args.len()).then(|| args.remove(index + 1));
+    args.remove(index);
+    value
+}
+
 fn main() {
+    // `rusty_capybara sim <field> [strategy] [lack of progress step]`
+    // explores a field file instead of running the robot. `--events <file>`
+    // anywhere after it writes what happened to the map for `replay`, and
+    // `--image <file.svg|file.png>` draws the map at the end.
+    let mut args: Vec<String> = std::env::args().collect();
+    let events = take_option(&mut args, "--events");
+    let image = take_option(&mut args, "--image");
+    if (3..=5).contains(&args.len()) && args[1] == "sim" {
+        let name = args.get(3).map_or("nearest-frontier", |name| name.as_str());
+        let Some(strategy) = map::strategy(name) else {
+            println!("Unknown strategy! Choose one of: {:?}", map::STRATEGIES);
+            return;
+        };
+        match Field::load(&args[2]) {
+            Ok(field) => {
+                let mut maze = Maze::new();
+                maze.set_strategy(strategy);
+                if let Some(path) = &events {
+                    if let Err(err) = maze.record_events(path) {
+                        println!("Error creating event file: {}", err);
+                        return;
+                    }
+                }
+                let mut simulator = Simulator::new(field);
+                if let Some(steps) = args.get(4).and_then(|steps| steps.parse().ok()) {
+                    simulator = simulator.lack_of_progress_at(steps);
+                }
+                let report = simulator.run(&mut maze, 1000);
+                if let Err(err) = maze.stop_recording() {
+                    println!("{}", err);
+                }
+                maze.print_maze();
+                println!("{}", report);
+                if let Some(path) = &image {
+                    match maze.export_images(path) {
+                        Ok(files) => println!("Map drawn to {:?}", files),
+                        Err(err) => println!("Error drawing map: {}", err),
+                    }
+                }
+            }
+            Err(err) => println!("Error loading field: {}", err),
+        }
+        return;
+    }
+    // `rusty_capybara replay <file>` steps through the events of a run.
+    if args.len() == 3 && args[1] == "replay" {
+        if let Err(err) = map::replay(&args[2]) {
+            println!("Error replaying events: {}", err);
+        }
+        return;
+    }
+    // `rusty_capybara gen [seed]` prints a random field to feed to `sim`.
+    if (2..=3).contains(&args.len()) && args[1] == "gen" {
+        let seed = match args.get(2) {
+            Some(seed) => seed.parse().unwrap_or(0),
+            None => std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map_or(0, |time| time.as_secs()),
+        };
+        match generator::generate(&generator::Settings::default(), seed) {
+            Ok(field) => print!("{}", field.render()),
+            Err(err) => println!("Error generating field: {}", err),
+        }
+        return;
+    }
+
     /*
     ██╗░░░██╗██╗░██████╗██╗░█████╗░███╗░░██╗
     ██║░░░██║██║██╔════╝██║██╔══██╗████╗░██║
@@ -127,7 +205,10 @@ fn main() {
 
     loop {
         for (i, tof) in tofs.iter_mut().enumerate() {
-            print!("  T{}: {}", i, tof.range().unwrap());
+            match tof.range() {
+                Ok(measurement) => print!("  T{}: {}", i, measurement),
+                Err(err) => print!("  T{}: {}", i, err),
+            }
         }
         println!();
     }
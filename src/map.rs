@@ -1,19 +1,66 @@
 #![allow(dead_code)]
+mod belief;
+mod clock;
+mod consistency;
+mod events;
+mod export;
+mod grid;
+mod localize;
+mod obstacle;
+mod planner;
+mod save;
+mod strategy;
+mod victims;
+mod view;
+
+use crate::scoring::{RunEvent, RunLog};
+use belief::Edge;
+pub use belief::WallModel;
+pub use clock::MissionClock;
+use consistency::Votes;
+pub use consistency::{Contradiction, Repair};
+pub use events::{replay, Event};
+use grid::Grid;
+pub use localize::Relocalization;
+pub use obstacle::{Obstacle, Quarter, QUARTERS};
+pub use planner::Costs;
 use std::collections::HashMap;
+use std::fs::File;
 use std::io::{self, BufRead};
+use std::path::PathBuf;
+pub use strategy::{strategy, ExplorationStrategy, STRATEGIES};
+use victims::VictimKey;
+pub use victims::{Victim, VictimKind};
+pub use view::Tile;
 
-type Position = (i32, i32);
+pub type Position = (i32, i32, i32);
 
-#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
-enum Direction {
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub enum Direction {
     Up,
     Down,
     Left,
     Right,
 }
 
+pub const DIRECTIONS: [Direction; 4] = [
+    Direction::Up,
+    Direction::Down,
+    Direction::Left,
+    Direction::Right,
+];
+
 impl Direction {
-    fn right(&self) -> Direction {
+    pub fn step(&self, pos: Position) -> Position {
+        match *self {
+            Direction::Up => (pos.0, pos.1 - 1, pos.2),
+            Direction::Down => (pos.0, pos.1 + 1, pos.2),
+            Direction::Left => (pos.0 - 1, pos.1, pos.2),
+            Direction::Right => (pos.0 + 1, pos.1, pos.2),
+        }
+    }
+
+    pub fn right(&self) -> Direction {
         match *self {
             Direction::Up => Direction::Right,
             Direction::Down => Direction::Left,
@@ -22,7 +69,7 @@ impl Direction {
         }
     }
 
-    fn left(&self) -> Direction {
+    pub fn left(&self) -> Direction {
         match *self {
             Direction::Up => Direction::Left,
             Direction::Down => Direction::Right,
@@ -31,7 +78,7 @@ impl Direction {
         }
     }
 
-    fn back(&self) -> Direction {
+    pub fn back(&self) -> Direction {
         match *self {
             Direction::Up => Direction::Down,
             Direction::Down => Direction::Up,
@@ -42,21 +89,30 @@ impl Direction {
 }
 
 #[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
-enum Kind {
+pub enum Kind {
     Start,
     Unknown,
     Empty,
     Checkpoint,
     Blue,
-    Victim,
     Ramp,
     Black,
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Side {
+    Wall,
+    Open,
+    Unknown,
+}
+
+#[derive(Clone)]
 struct Cell {
     pos: Position,
     kind: Kind,
+    walls: HashMap<Direction, Side>,
     neighbors: HashMap<Direction, Position>,
+    obstacle: Option<Obstacle>,
 }
 
 impl Cell {
@@ -64,148 +120,231 @@ impl Cell {
         Cell {
             pos: pos,
             kind: kind,
+            walls: HashMap::new(),
             neighbors: HashMap::new(),
+            obstacle: None,
         }
     }
 
+    fn side(&self, direction: Direction) -> Side {
+        *self.walls.get(&direction).unwrap_or(&Side::Unknown)
+    }
+
     fn add_neighbor(&mut self, direction: Direction, neighbor_pos: Position) {
-        if self.neighbors.contains_key(&direction) {
+        self.walls.insert(direction, Side::Open);
+        // Nothing leads out of a black tile.
+        if self.kind == Kind::Black || self.neighbors.contains_key(&direction) {
             return;
         }
         self.neighbors.insert(direction, neighbor_pos);
     }
+
+    fn add_wall(&mut self, direction: Direction) {
+        self.walls.insert(direction, Side::Wall);
+        self.neighbors.remove(&direction);
+    }
+
+    fn open_neighbor(&self, direction: Direction) -> Option<&Position> {
+        if self.side(direction) != Side::Open {
+            return None;
+        }
+        self.neighbors.get(&direction)
+    }
+}
+
+// Rescue kits the robot starts a run with.
+const RESCUE_KITS: u8 = 12;
+
+// Everything the robot has to forget when it is sent back to a checkpoint.
+#[derive(Clone)]
+struct Snapshot {
+    dir: Direction,
+    pos: Position,
+    cells: Grid,
+    path: Vec<Position>,
+    victims: HashMap<VictimKey, Victim>,
+    beliefs: HashMap<Edge, f32>,
+    scans: HashMap<Edge, Votes>,
+    rescue_kits: u8,
 }
 
 pub struct Maze {
     dir: Direction,
     pos: Position,
     last_checkpoint: Position,
-    cells: HashMap<Position, Cell>,
+    cells: Grid,
     path: Vec<Position>,
+    victims: HashMap<VictimKey, Victim>,
+    beliefs: HashMap<Edge, f32>,
+    wall_model: WallModel,
+    scans: HashMap<Edge, Votes>,
+    repair: Repair,
+    contradictions: Vec<Contradiction>,
+    rescue_kits: u8,
+    checkpoint: Snapshot,
+    costs: Costs,
+    clock: Option<MissionClock>,
+    returning: bool,
+    autosave: Option<PathBuf>,
+    run: RunLog,
+    events: Vec<Event>,
+    event_file: Option<File>,
+    event_error: Option<io::Error>,
+    replaying: bool,
+    strategy: Box<dyn ExplorationStrategy>,
 }
 
 impl Maze {
     pub fn new() -> Self {
-        let mut cells = HashMap::new();
-        cells.insert((0, 0), Cell::new((0, 0), Kind::Start));
+        let mut cells = Grid::default();
+        cells.insert((0, 0, 0), Cell::new((0, 0, 0), Kind::Start));
+        let checkpoint = Snapshot {
+            dir: Direction::Up,
+            pos: (0, 0, 0),
+            cells: cells.clone(),
+            path: vec![],
+            victims: HashMap::new(),
+            beliefs: HashMap::new(),
+            scans: HashMap::new(),
+            rescue_kits: RESCUE_KITS,
+        };
         Maze {
             dir: Direction::Up,
-            pos: (0, 0),
-            last_checkpoint: (0, 0),
+            pos: (0, 0, 0),
+            last_checkpoint: (0, 0, 0),
             cells,
             path: vec![],
+            victims: HashMap::new(),
+            beliefs: HashMap::new(),
+            wall_model: WallModel::default(),
+            scans: HashMap::new(),
+            repair: Repair::TrustLatest,
+            contradictions: vec![],
+            rescue_kits: RESCUE_KITS,
+            checkpoint,
+            costs: Costs::default(),
+            clock: None,
+            returning: false,
+            autosave: None,
+            run: RunLog::default(),
+            events: vec![],
+            event_file: None,
+            event_error: None,
+            replaying: false,
+            strategy: Box::new(strategy::NearestFrontier),
         }
     }
 
-    fn coordinate_to_direction(&self, pos: Position) -> Direction {
-        if pos.0 == self.pos.0 && pos.1 == self.pos.1 - 1 {
-            return Direction::Up;
-        } else if pos.0 == self.pos.0 && pos.1 == self.pos.1 + 1 {
-            return Direction::Down;
-        } else if pos.0 == self.pos.0 - 1 && pos.1 == self.pos.1 {
-            return Direction::Left;
-        } else if pos.0 == self.pos.0 + 1 && pos.1 == self.pos.1 {
-            return Direction::Right;
+    pub fn set_strategy(&mut self, strategy: Box<dyn ExplorationStrategy>) {
+        self.strategy = strategy;
+        self.path.clear();
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            dir: self.dir,
+            pos: self.pos,
+            cells: self.cells.clone(),
+            path: self.path.clone(),
+            victims: self.victims.clone(),
+            beliefs: self.beliefs.clone(),
+            scans: self.scans.clone(),
+            rescue_kits: self.rescue_kits,
         }
-        panic!("Invalid position!");
+    }
+
+    fn restore(&mut self, snapshot: Snapshot) {
+        self.dir = snapshot.dir;
+        self.pos = snapshot.pos;
+        self.cells = snapshot.cells;
+        self.path = snapshot.path;
+        self.victims = snapshot.victims;
+        self.beliefs = snapshot.beliefs;
+        self.scans = snapshot.scans;
+        self.rescue_kits = snapshot.rescue_kits;
+    }
+
+    pub fn start_clock(&mut self, clock: MissionClock) {
+        self.clock = Some(clock);
+        self.returning = false;
+    }
+
+    pub fn set_costs(&mut self, costs: Costs) {
+        self.costs = costs;
+        self.path.clear();
+    }
+
+    fn coordinate_to_direction(&self, pos: Position) -> Option<Direction> {
+        let cell = self.cells.get(&self.pos)?;
+        DIRECTIONS
+            .into_iter()
+            .find(|direction| cell.open_neighbor(*direction) == Some(&pos))
     }
 
     fn get_direction(&mut self) -> Option<Direction> {
+        if !self.returning && self.out_of_time() {
+            self.returning = true;
+            self.path.clear();
+            self.emit(Event::Return);
+        }
+        // A wall or a black tile found since the path was planned, or the
+        // robot being moved back off a black tile, leaves the rest of it
+        // useless.
+        if !self.path.is_empty() && !self.path_is_clear() {
+            let path = std::mem::take(&mut self.path);
+            self.emit(Event::Blocked(path));
+        }
         if !self.path.is_empty() {
             let coordinate = self.path.remove(0);
-            return Some(self.coordinate_to_direction(coordinate));
-        }
-        let right_direction = self.dir.right();
-        let straight_direction = self.dir;
-        let left_direction = self.dir.left();
-        let back_direction = self.dir.back();
-
-        if let Some(cell) = self.cells.get(&self.pos) {
-            if let Some(right_neighbor_pos) = cell.neighbors.get(&right_direction) {
-                if let Some(right_neighbor_cell) = self.cells.get(right_neighbor_pos) {
-                    if right_neighbor_cell.kind == Kind::Unknown {
-                        return Some(right_direction);
-                    }
-                }
-            }
-            if let Some(straight_neighbor_pos) = cell.neighbors.get(&straight_direction) {
-                if let Some(straight_neighbor_cell) = self.cells.get(straight_neighbor_pos) {
-                    if straight_neighbor_cell.kind == Kind::Unknown {
-                        return Some(straight_direction);
-                    }
-                }
-            }
-            if let Some(left_neighbor_pos) = cell.neighbors.get(&left_direction) {
-                if let Some(left_neighbor_cell) = self.cells.get(left_neighbor_pos) {
-                    if left_neighbor_cell.kind == Kind::Unknown {
-                        return Some(left_direction);
-                    }
-                }
-            }
-            if let Some(back_neighbor_pos) = cell.neighbors.get(&back_direction) {
-                if let Some(back_neighbor_cell) = self.cells.get(back_neighbor_pos) {
-                    if back_neighbor_cell.kind == Kind::Unknown {
-                        return Some(back_direction);
-                    }
-                }
-            }
+            return self.coordinate_to_direction(coordinate);
         }
 
-        let path = self.bfs(Kind::Unknown);
-        if path.is_empty() {
-            let path = self.bfs(Kind::Start);
-            if !path.is_empty() {
-                self.path = path;
-                let coordinate = self.path.remove(0);
-                return Some(self.coordinate_to_direction(coordinate));
+        // Exploring only ends when no unexplored tile can be reached: a path
+        // the strategy picked that can't be driven gives way to the cheapest
+        // unexplored tile, and that to the start.
+        let explore = if self.returning {
+            None
+        } else {
+            match self.strategy.next_path(self) {
+                Some(path) => self.drivable(Some(path)).or_else(|| {
+                    let path = self.plan(Kind::Unknown).map(|route| route.path);
+                    self.drivable(path)
+                }),
+                None => None,
             }
-            return None;
-        }
+        };
+        let path = match explore {
+            Some(path) => path,
+            None => {
+                let path = self.plan(Kind::Start).map(|route| route.path);
+                self.drivable(path)?
+            }
+        };
         self.path = path;
+        self.emit(Event::Path(self.path.clone()));
         let coordinate = self.path.remove(0);
-        Some(self.coordinate_to_direction(coordinate))
-    }
-
-    fn bfs(&mut self, tar: Kind) -> Vec<Position> {
-        let mut queue = vec![self.pos];
-        let mut visited = vec![self.pos];
-        let mut parent = HashMap::new();
-        let mut found = false;
-        let mut target = (0, 0);
-        while !queue.is_empty() {
-            let current = queue.remove(0);
-            if let Some(cell) = self.cells.get(&current) {
-                if cell.kind == tar {
-                    found = true;
-                    target = current;
-                    break;
-                }
-                for neighbor in cell.neighbors.values() {
-                    if !visited.contains(neighbor) {
-                        if let Some(neighbor_cell) = self.cells.get(neighbor) {
-                            if neighbor_cell.kind != Kind::Black {
-                                queue.push(*neighbor);
-                                visited.push(*neighbor);
-                                parent.insert(*neighbor, current);
-                            }
-                        }
-                    }
-                }
-            }
-        }
+        self.coordinate_to_direction(coordinate)
+    }
 
-        let mut path = vec![];
-        if found {
-            let mut current = target;
-            while current != self.pos {
-                path.push(current);
-                current = parent[&current];
-            }
-            path.reverse();
+    // The path, if there is one to drive and it can be driven from where
+    // the robot is.
+    fn drivable(&mut self, path: Option<Vec<Position>>) -> Option<Vec<Position>> {
+        let path = path.filter(|path| !path.is_empty())?;
+        if !self.is_clear(&path) {
+            self.emit(Event::Blocked(path));
+            return None;
         }
+        Some(path)
+    }
 
-        println!("path: {:?} to: {:?}", path, tar);
-        path
+    fn out_of_time(&self) -> bool {
+        let Some(clock) = &self.clock else {
+            return false;
+        };
+        match self.plan(Kind::Start) {
+            Some(route) => clock.must_return(route.cost),
+            None => false,
+        }
     }
 
     fn dir_to_dir_relative_to_robot(&self, dir: Direction) -> Direction {
@@ -227,161 +366,420 @@ impl Maze {
                 Direction::Up => Direction::Left,
                 Direction::Down => Direction::Right,
                 Direction::Left => Direction::Down,
-                Direction::Right => Direction::Right,
+                Direction::Right => Direction::Up,
             },
         }
     }
 
-    fn move_one(&mut self) -> Option<Direction> {
+    pub fn move_one(&mut self) -> Option<Direction> {
         if let Some(direction) = self.get_direction() {
             let dir_rel = self.dir_to_dir_relative_to_robot(direction);
+            let heading = self.dir;
             self.dir = direction;
-
-            match direction {
-                Direction::Up => self.pos.1 -= 1,
-                Direction::Down => self.pos.1 += 1,
-                Direction::Left => self.pos.0 -= 1,
-                Direction::Right => self.pos.0 += 1,
+            self.pos = self.neighbor(self.pos, direction);
+            let mut cost = self.kind_cost(heading, direction, self.pos);
+
+            // A known ramp is driven in one go, so the robot ends up on the
+            // tile at its other end.
+            if let Some(cell) = self.cells.get(&self.pos) {
+                if cell.kind == Kind::Ramp {
+                    if let Some(exit) = cell.open_neighbor(direction) {
+                        self.run.push(RunEvent::Ramp(self.pos));
+                        self.pos = *exit;
+                        cost += self.kind_cost(direction, direction, self.pos);
+                        if self.path.first() == Some(exit) {
+                            self.path.remove(0);
+                        }
+                    }
+                }
+            }
+            if let Some(clock) = &mut self.clock {
+                clock.tick(cost);
             }
             if let Some(cell) = self.cells.get_mut(&self.pos) {
                 if cell.kind == Kind::Unknown {
                     cell.kind = Kind::Empty;
                 }
             }
+            self.run.push(RunEvent::Visited(self.pos));
+            self.emit(Event::Move {
+                pos: self.pos,
+                dir: self.dir,
+            });
+            if let Some(path) = &self.autosave {
+                if let Err(err) = self.save(path) {
+                    println!("Error saving maze: {}", err);
+                }
+            }
 
             Some(dir_rel)
         } else {
+            let home = self.cells.get(&self.pos).map(|cell| cell.kind) == Some(Kind::Start);
+            if home && self.run.events.last() != Some(&RunEvent::Exit) {
+                self.run.push(RunEvent::Exit);
+            }
             None
         }
     }
 
+    pub fn run_log(&self) -> &RunLog {
+        &self.run
+    }
+
+    fn kind_cost(&self, heading: Direction, direction: Direction, pos: Position) -> u32 {
+        let unknown = Cell::new(pos, Kind::Unknown);
+        let cell = self.cells.get(&pos).unwrap_or(&unknown);
+        self.enter_cost(heading, direction, cell)
+    }
+
     fn add_cell(&mut self, direction: Direction) {
-        let cell_pos = match direction {
-            Direction::Up => (self.pos.0, self.pos.1 - 1),
-            Direction::Down => (self.pos.0, self.pos.1 + 1),
-            Direction::Left => (self.pos.0 - 1, self.pos.1),
-            Direction::Right => (self.pos.0 + 1, self.pos.1),
-        };
+        self.open_side(self.pos, direction);
+    }
 
+    fn add_wall(&mut self, direction: Direction) {
+        self.close_side(self.pos, direction);
+    }
+
+    fn neighbor(&self, pos: Position, direction: Direction) -> Position {
+        match self
+            .cells
+            .get(&pos)
+            .and_then(|cell| cell.neighbors.get(&direction))
+        {
+            Some(neighbor) => *neighbor,
+            None => direction.step(pos),
+        }
+    }
+
+    fn open_side(&mut self, pos: Position, direction: Direction) {
+        if let Some(cell) = self.cells.get(&pos) {
+            if cell.open_neighbor(direction).is_some() {
+                return;
+            }
+        }
+        self.link(pos, direction, direction.step(pos));
+    }
+
+    fn link(&mut self, pos: Position, direction: Direction, cell_pos: Position) {
         if let Some(cell) = self.cells.get_mut(&cell_pos) {
-            if !cell.neighbors.contains_key(&direction.back()) {
-                cell.add_neighbor(direction.back(), self.pos);
-                let current_cell = self.cells.get_mut(&self.pos).unwrap();
+            if cell.open_neighbor(direction.back()).is_none() {
+                cell.add_neighbor(direction.back(), pos);
+                let current_cell = self.cells.get_mut(&pos).unwrap();
                 current_cell.add_neighbor(direction, cell_pos);
             }
         } else {
-            if cell_pos.0 < 0 {
-                self.cells.iter_mut().for_each(|(_, cell)| {
-                    cell.pos.0 += 1;
-                });
-            } else if cell_pos.1 < 0 {
-                self.cells.iter_mut().for_each(|(_, cell)| {
-                    cell.pos.1 += 1;
-                });
-            }
             let mut new_cell = Cell::new(cell_pos, Kind::Unknown);
+            // The new tile shares its other edges with tiles that may already
+            // have seen a wall there.
+            for side in DIRECTIONS {
+                if let Some(other) = self.cells.get(&side.step(cell_pos)) {
+                    if other.side(side.back()) == Side::Wall {
+                        new_cell.add_wall(side);
+                    }
+                }
+            }
             self.cells
-                .get_mut(&self.pos)
+                .get_mut(&pos)
                 .unwrap()
                 .add_neighbor(direction, cell_pos);
-            new_cell.add_neighbor(direction.back(), self.pos);
+            new_cell.add_neighbor(direction.back(), pos);
             self.cells.insert(cell_pos, new_cell);
         }
     }
 
-    pub fn robot_scan(&mut self, front: bool, right: bool, left: bool, back: bool) {
-        if front {
-            self.add_cell(self.dir);
+    // Links `pos` and `other` both ways, replacing what either of them was
+    // linked to on that side. A tile the robot never reached that loses its
+    // link this way was only a guess, and is dropped.
+    fn join(&mut self, pos: Position, direction: Direction, other: Position) {
+        let old = self
+            .cells
+            .get_mut(&pos)
+            .and_then(|cell| cell.neighbors.remove(&direction));
+        let mut stale = vec![];
+        if let Some(cell) = self.cells.get_mut(&other) {
+            stale.extend(cell.neighbors.remove(&direction.back()));
+            cell.add_neighbor(direction.back(), pos);
+            self.cells
+                .get_mut(&pos)
+                .unwrap()
+                .add_neighbor(direction, other);
+        } else {
+            self.link(pos, direction, other);
+        }
+        stale.extend(old);
+        for tile in stale {
+            let unknown = self.cells.get(&tile).map(|cell| cell.kind) == Some(Kind::Unknown);
+            if tile != pos && tile != other && unknown {
+                self.forget(tile);
+            }
+        }
+    }
+
+    // Takes a tile off the map, along with the links to it.
+    fn forget(&mut self, pos: Position) {
+        let Some(cell) = self.cells.remove(&pos) else {
+            return;
+        };
+        for (direction, other) in cell.neighbors {
+            if let Some(other) = self.cells.get_mut(&other) {
+                if other.neighbors.get(&direction.back()) == Some(&pos) {
+                    other.neighbors.remove(&direction.back());
+                }
+            }
         }
-        if right {
-            self.add_cell(self.dir.right());
+    }
+
+    fn close_side(&mut self, pos: Position, direction: Direction) {
+        let neighbor_pos = self.neighbor(pos, direction);
+        if let Some(cell) = self.cells.get_mut(&pos) {
+            cell.add_wall(direction);
         }
-        if left {
-            self.add_cell(self.dir.left());
+        if let Some(cell) = self.cells.get_mut(&neighbor_pos) {
+            cell.add_wall(direction.back());
         }
-        if back {
-            self.add_cell(self.dir.back());
+    }
+
+    fn edge(&self, pos: Position, direction: Direction) -> Option<Side> {
+        let near = self.cells.get(&pos).map(|cell| cell.side(direction));
+        let far = self
+            .cells
+            .get(&direction.step(pos))
+            .map(|cell| cell.side(direction.back()));
+        match (near, far) {
+            (None, None) => None,
+            (Some(Side::Unknown), Some(side)) | (Some(side), _) => Some(side),
+            (None, Some(side)) => Some(side),
+        }
+    }
+
+    pub fn robot_scan(&mut self, front: bool, right: bool, left: bool, back: bool) {
+        let sides =
+            [front, right, left, back].map(|open| if open { Side::Open } else { Side::Wall });
+        self.emit(Event::Scan {
+            pos: self.pos,
+            dir: self.dir,
+            sides,
+        });
+        let scan = [
+            (front, self.dir),
+            (right, self.dir.right()),
+            (left, self.dir.left()),
+            (back, self.dir.back()),
+        ];
+        for (open, direction) in scan {
+            if open {
+                self.record_scan(direction, Side::Open);
+                self.add_cell(direction);
+            } else {
+                self.record_scan(direction, Side::Wall);
+                self.add_wall(direction);
+            }
+        }
+        self.check_map(&[self.pos]);
+        self.refresh_checkpoint();
+    }
+
+    // The robot starts on a checkpoint it hasn't scanned yet, so what it
+    // sees there has to be kept for when it is put back. Once every side of
+    // the checkpoint is in the snapshot it stays as it is, later scans there
+    // could come from a robot that is lost.
+    fn refresh_checkpoint(&mut self) {
+        let pos = self.last_checkpoint;
+        let scanned = DIRECTIONS.into_iter().all(|direction| {
+            self.checkpoint
+                .scans
+                .contains_key(&self.edge_key(pos, direction))
+        });
+        if self.pos == pos && !scanned {
+            self.checkpoint = self.snapshot();
         }
     }
 
     pub fn add_checkpoint(&mut self) {
         self.cells.get_mut(&self.pos).unwrap().kind = Kind::Checkpoint;
         self.last_checkpoint = self.pos;
+        self.checkpoint = self.snapshot();
+        self.run.push(RunEvent::Checkpoint(self.pos));
+        self.emit(Event::Checkpoint(self.pos));
     }
 
-    pub fn add_victim(&mut self) {
-        self.cells.get_mut(&self.pos).unwrap().kind = Kind::Victim;
-    }
-
-    pub fn add_ramp(&mut self) {
-        self.cells.get_mut(&self.pos).unwrap().kind = Kind::Ramp;
+    pub fn add_ramp(&mut self, up: bool) {
+        self.emit(Event::Ramp {
+            pos: self.pos,
+            dir: self.dir,
+            up,
+        });
+        // A ramp is kept on the floor at its bottom, like the tile it leads
+        // to from there, so the map ends up the same whichever way the robot
+        // drives it first. The far end keeps the x/y of the tile straight
+        // ahead, only on the other floor.
+        let (ramp, exit) = if up {
+            let (x, y, level) = self.dir.step(self.pos);
+            (self.pos, (x, y, level + 1))
+        } else {
+            let top = self.neighbor(self.pos, self.dir.back());
+            let (x, y, level) = self.pos;
+            let ramp = (x, y, level - 1);
+            self.forget(self.pos);
+            if !self.cells.contains_key(&ramp) {
+                self.cells.insert(ramp, Cell::new(ramp, Kind::Unknown));
+            }
+            self.join(top, self.dir, ramp);
+            (ramp, self.dir.step(ramp))
+        };
+        self.cells.get_mut(&ramp).unwrap().kind = Kind::Ramp;
+        self.run.push(RunEvent::Ramp(ramp));
+        self.close_side(ramp, self.dir.left());
+        self.close_side(ramp, self.dir.right());
+        self.join(ramp, self.dir, exit);
+        self.path.clear();
+
+        self.pos = exit;
+        let cell = self.cells.get_mut(&exit).unwrap();
+        if cell.kind == Kind::Unknown {
+            cell.kind = Kind::Empty;
+        }
+        self.run.push(RunEvent::Visited(exit));
+        self.check_map(&[ramp, exit]);
     }
 
     pub fn add_blue(&mut self) {
         self.cells.get_mut(&self.pos).unwrap().kind = Kind::Blue;
+        self.emit(Event::Mark {
+            pos: self.pos,
+            kind: Kind::Blue,
+        });
     }
 
     pub fn add_black(&mut self) {
-        self.cells.get_mut(&self.pos).unwrap().kind = Kind::Black;
-        match self.dir {
-            Direction::Up => self.pos.1 += 1,
-            Direction::Down => self.pos.1 -= 1,
-            Direction::Left => self.pos.0 += 1,
-            Direction::Right => self.pos.0 -= 1,
-        }
+        let black = self.pos;
+        self.emit(Event::Mark {
+            pos: black,
+            kind: Kind::Black,
+        });
+        self.pos = self.neighbor(black, self.dir.back());
+        let cell = self.cells.get_mut(&black).unwrap();
+        cell.kind = Kind::Black;
+        cell.neighbors.clear();
+        self.check_map(&[black]);
     }
 
     pub fn lack_of_progress(&mut self) {
-        self.pos = self.last_checkpoint;
+        self.restore(self.checkpoint.clone());
+        self.run.push(RunEvent::LackOfProgress);
+        self.emit(Event::LackOfProgress);
     }
 
     pub fn print_maze(&self) {
+        print!("{}", self.render());
+    }
+
+    /// The map as [`Maze::print_maze`] draws it.
+    pub fn render(&self) -> String {
+        let mut levels: Vec<i32> = self.cells.keys().map(|pos| pos.2).collect();
+        levels.sort();
+        levels.dedup();
+        let mut out = String::new();
+        for level in levels {
+            out.push_str(&format!("level {}\n", level));
+            self.render_level(level, &mut out);
+        }
+        out
+    }
+
+    fn render_level(&self, level: i32, out: &mut String) {
         let mut min_x = std::i32::MAX;
         let mut max_x = std::i32::MIN;
         let mut min_y = std::i32::MAX;
         let mut max_y = std::i32::MIN;
 
-        for (pos, _) in &self.cells {
+        for (pos, _) in self.cells.iter().filter(|(pos, _)| pos.2 == level) {
             min_x = min_x.min(pos.0);
             max_x = max_x.max(pos.0);
             min_y = min_y.min(pos.1);
             max_y = max_y.max(pos.1);
         }
 
-        for y in (min_y - 1)..=(max_y + 1) {
-            for x in (min_x - 1)..=(max_x + 1) {
-                let pos = (x, y);
-                let cell = self.cells.get(&pos);
-
-                let symbol = match cell {
-                    Some(cell) => match cell.kind {
-                        Kind::Start => 'S',
-                        Kind::Unknown => '?',
-                        Kind::Empty => ' ',
-                        Kind::Checkpoint => 'C',
-                        Kind::Blue => 'B',
-                        Kind::Victim => 'V',
-                        Kind::Ramp => 'R',
-                        Kind::Black => '█',
-                    },
-                    None => '.',
-                };
-
-                if pos == self.pos {
-                    let arrow = match self.dir {
-                        Direction::Up => '^',
-                        Direction::Down => 'v',
-                        Direction::Left => '<',
-                        Direction::Right => '>',
-                    };
-                    print!("{} ", arrow);
-                } else if self.path.contains(&(x, y)) {
-                    print!("* ");
+        for y in min_y..=max_y {
+            let mut walls = String::new();
+            let mut tiles = String::new();
+            for x in min_x..=max_x {
+                let pos = (x, y, level);
+                walls.push('+');
+                walls.push(self.edge_symbol(pos, Direction::Up));
+                tiles.push(self.edge_symbol(pos, Direction::Left));
+                tiles.push(self.symbol(pos));
+            }
+            walls.push('+');
+            tiles.push(self.edge_symbol((max_x, y, level), Direction::Right));
+            out.push_str(&walls);
+            out.push('\n');
+            out.push_str(&tiles);
+            out.push('\n');
+        }
+
+        let mut walls = String::new();
+        for x in min_x..=max_x {
+            walls.push('+');
+            walls.push(self.edge_symbol((x, max_y, level), Direction::Down));
+        }
+        walls.push('+');
+        out.push_str(&walls);
+        out.push('\n');
+    }
+
+    fn edge_symbol(&self, pos: Position, direction: Direction) -> char {
+        let victim = self
+            .victims
+            .get(&(pos, direction))
+            .or_else(|| self.victims.get(&(direction.step(pos), direction.back())));
+        if let Some(victim) = victim {
+            return victim.kind.symbol();
+        }
+        match (self.edge(pos, direction), direction) {
+            (Some(Side::Wall), Direction::Up | Direction::Down) => '-',
+            (Some(Side::Wall), Direction::Left | Direction::Right) => '|',
+            (Some(Side::Unknown), _) => '?',
+            (Some(Side::Open) | None, _) => ' ',
+        }
+    }
+
+    fn symbol(&self, pos: Position) -> char {
+        if pos == self.pos {
+            return match self.dir {
+                Direction::Up => '^',
+                Direction::Down => 'v',
+                Direction::Left => '<',
+                Direction::Right => '>',
+            };
+        }
+        if self.path.contains(&pos) {
+            return '*';
+        }
+        match self.cells.get(&pos) {
+            Some(Cell {
+                obstacle: Some(obstacle),
+                ..
+            }) => {
+                if DIRECTIONS
+                    .iter()
+                    .any(|direction| obstacle.passable(*direction))
+                {
+                    'o'
                 } else {
-                    print!("{} ", symbol);
+                    'O'
                 }
             }
-            println!();
+            Some(cell) => match cell.kind {
+                Kind::Start => 'S',
+                Kind::Unknown => '?',
+                Kind::Empty => ' ',
+                Kind::Checkpoint => 'C',
+                Kind::Blue => 'B',
+                Kind::Ramp => 'R',
+                Kind::Black => '█',
+            },
+            None => '.',
         }
     }
 
@@ -415,6 +813,11 @@ impl Maze {
                     }
                 }
             }
+            for (c, direction) in ['U', 'D', 'L', 'R'].into_iter().zip(DIRECTIONS) {
+                if !input.contains(c) {
+                    maze.add_wall(direction);
+                }
+            }
 
             if let Some(dir) = maze.move_one() {
                 println!("Moved {:?}", dir);
@@ -443,3 +846,92 @@ impl Maze {
         maze.print_maze();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Always picks the same path, whether or not it can be driven.
+    struct Fixed(Option<Vec<Position>>);
+
+    impl ExplorationStrategy for Fixed {
+        fn name(&self) -> &'static str {
+            "fixed"
+        }
+
+        fn next_path(&self, _maze: &Maze) -> Option<Vec<Position>> {
+            self.0.clone()
+        }
+    }
+
+    // Runs `moves` on a new maze exploring with `path`, and gives back what
+    // the last move returned along with the events.
+    fn run(
+        name: &str,
+        path: Option<Vec<Position>>,
+        moves: impl FnOnce(&mut Maze) -> Option<Direction>,
+    ) -> (Maze, Option<Direction>, Vec<Event>) {
+        let file = std::env::temp_dir().join(format!("{}-{}.txt", std::process::id(), name));
+        let mut maze = Maze::new();
+        maze.set_strategy(Box::new(Fixed(path)));
+        maze.record_events(&file).unwrap();
+        let moved = moves(&mut maze);
+        maze.stop_recording().unwrap();
+        std::fs::remove_file(&file).unwrap();
+        let events = maze.events().to_vec();
+        (maze, moved, events)
+    }
+
+    #[test]
+    fn a_blocked_strategy_gives_way_to_the_nearest_frontier() {
+        let (maze, moved, events) = run("frontier", Some(vec![(1, 0, 0)]), |maze| {
+            maze.robot_scan(true, false, false, false);
+            maze.move_one()
+        });
+        assert_eq!(moved, Some(Direction::Up));
+        assert_eq!(maze.pos, (0, -1, 0));
+        assert_eq!(
+            events[1..3],
+            [
+                Event::Blocked(vec![(1, 0, 0)]),
+                Event::Path(vec![(0, -1, 0)])
+            ]
+        );
+    }
+
+    #[test]
+    fn with_nothing_left_to_explore_the_robot_goes_home() {
+        let (maze, moved, events) = run("home", Some(vec![(1, -1, 0)]), |maze| {
+            maze.robot_scan(true, false, false, false);
+            maze.pos = (0, -1, 0);
+            maze.cells.get_mut(&maze.pos).unwrap().kind = Kind::Empty;
+            maze.robot_scan(false, false, false, true);
+            maze.move_one()
+        });
+        assert_eq!(moved, Some(Direction::Down));
+        assert_eq!(maze.pos, (0, 0, 0));
+        assert_eq!(
+            events[2..4],
+            [
+                Event::Blocked(vec![(1, -1, 0)]),
+                Event::Path(vec![(0, 0, 0)])
+            ]
+        );
+    }
+
+    #[test]
+    fn a_path_that_gets_blocked_is_dropped() {
+        let (maze, moved, events) = run("dropped", None, |maze| {
+            maze.robot_scan(true, false, false, false);
+            maze.path = vec![(0, -1, 0), (0, -2, 0)];
+            maze.robot_scan(false, false, false, false);
+            maze.move_one()
+        });
+        assert_eq!(moved, None);
+        assert!(maze.path.is_empty());
+        assert_eq!(
+            events.last(),
+            Some(&Event::Blocked(vec![(0, -1, 0), (0, -2, 0)]))
+        );
+    }
+}
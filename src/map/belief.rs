@@ -0,0 +1,132 @@
+use super::{Direction, Event, Maze, Position, Side};
+
+/// Edge between two tiles, named by the smaller of its two (tile, side) pairs
+/// so that both tiles look up the same belief.
+pub(super) type Edge = (Position, Direction);
+
+/// How a VL6180X distance turns into evidence about a wall.
+///
+/// Distances are in millimetres from the sensor. A reading up to `wall` adds
+/// `hit` to the log-odds that there is a wall, one from `open` on (including
+/// no reading at all) takes `miss` away, and anything in between is ignored.
+/// A wall goes on the map once the log-odds reach `commit` and comes off it
+/// once they drop to `-commit`. Beliefs never grow past `limit`, so a few
+/// readings are always enough to change the map again.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct WallModel {
+    pub wall: u8,
+    pub open: u8,
+    pub hit: f32,
+    pub miss: f32,
+    pub commit: f32,
+    pub limit: f32,
+}
+
+impl Default for WallModel {
+    fn default() -> Self {
+        WallModel {
+            wall: 120,
+            open: 180,
+            hit: 1.0,
+            miss: 1.0,
+            commit: 2.0,
+            limit: 4.0,
+        }
+    }
+}
+
+impl WallModel {
+    fn evidence(&self, range: Option<u8>) -> f32 {
+        match range {
+            Some(range) if range <= self.wall => self.hit,
+            Some(range) if range < self.open => 0.0,
+            _ => -self.miss,
+        }
+    }
+}
+
+impl Maze {
+    pub fn set_wall_model(&mut self, model: WallModel) {
+        self.wall_model = model;
+    }
+
+    pub(super) fn edge_key(&self, pos: Position, direction: Direction) -> Edge {
+        let other = (self.neighbor(pos, direction), direction.back());
+        (pos, direction).min(other)
+    }
+
+    /// Log-odds that there is a wall on `direction` of `pos`, 0 if it was
+    /// never seen.
+    pub fn wall_belief(&self, pos: Position, direction: Direction) -> f32 {
+        let key = self.edge_key(pos, direction);
+        self.beliefs.get(&key).copied().unwrap_or(0.0)
+    }
+
+    /// Adds one reading of the side of `pos` towards `direction`, which can
+    /// have been taken from either of the two tiles it separates, and puts
+    /// the wall on or off the map once the belief is strong enough.
+    ///
+    /// Returns `true` if a wall or an opening that was already on the map
+    /// had to be taken back.
+    pub fn observe_side(&mut self, pos: Position, direction: Direction, range: Option<u8>) -> bool {
+        let model = self.wall_model;
+        let key = self.edge_key(pos, direction);
+        let belief = self.beliefs.entry(key).or_insert(0.0);
+        *belief = (*belief + model.evidence(range)).clamp(-model.limit, model.limit);
+        let belief = *belief;
+
+        let side = self
+            .cells
+            .get(&pos)
+            .map_or(Side::Unknown, |cell| cell.side(direction));
+        let wanted = if belief >= model.commit {
+            Side::Wall
+        } else if belief <= -model.commit {
+            Side::Open
+        } else {
+            return false;
+        };
+        if side == wanted {
+            return false;
+        }
+        match wanted {
+            Side::Wall => self.close_side(pos, direction),
+            _ => self.open_side(pos, direction),
+        }
+        side != Side::Unknown
+    }
+
+    /// Like [`Maze::robot_scan`], but from the raw distances read by the four
+    /// sensors. `None` is a sensor that saw nothing in range.
+    ///
+    /// If one of the readings changes a side that was already on the map,
+    /// the planned path is dropped so the next move plans a new one.
+    pub fn robot_ranges(
+        &mut self,
+        front: Option<u8>,
+        right: Option<u8>,
+        left: Option<u8>,
+        back: Option<u8>,
+    ) {
+        self.emit(Event::Ranges {
+            pos: self.pos,
+            dir: self.dir,
+            ranges: [front, right, left, back],
+        });
+        let scan = [
+            (front, self.dir),
+            (right, self.dir.right()),
+            (left, self.dir.left()),
+            (back, self.dir.back()),
+        ];
+        let mut revised = false;
+        for (range, direction) in scan {
+            revised |= self.observe_side(self.pos, direction, range);
+        }
+        if revised {
+            self.path.clear();
+        }
+        self.check_map(&[self.pos]);
+        self.refresh_checkpoint();
+    }
+}
@@ -0,0 +1,74 @@
+use std::time::{Duration, Instant};
+
+enum Source {
+    Wall(Instant),
+    Simulated(Duration),
+}
+
+/// Keeps track of how much of the run is left and turns planner costs into
+/// an estimate of how long driving a route will take.
+pub struct MissionClock {
+    source: Source,
+    limit: Duration,
+    per_cost: Duration,
+    margin: Duration,
+}
+
+impl MissionClock {
+    /// Starts counting now, against a run of `limit`.
+    pub fn new(limit: Duration) -> Self {
+        MissionClock {
+            source: Source::Wall(Instant::now()),
+            limit,
+            per_cost: Duration::from_millis(100),
+            margin: Duration::from_secs(20),
+        }
+    }
+
+    /// A clock that only moves when the maze moves, by the estimated time of
+    /// each move. Meant for running the explorer off the robot.
+    pub fn simulated(limit: Duration) -> Self {
+        MissionClock {
+            source: Source::Simulated(Duration::ZERO),
+            ..MissionClock::new(limit)
+        }
+    }
+
+    /// Time one unit of planner cost takes to drive.
+    pub fn per_cost(mut self, per_cost: Duration) -> Self {
+        self.per_cost = per_cost;
+        self
+    }
+
+    /// Extra time kept in hand on top of the estimated way home.
+    pub fn margin(mut self, margin: Duration) -> Self {
+        self.margin = margin;
+        self
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        match self.source {
+            Source::Wall(start) => start.elapsed(),
+            Source::Simulated(elapsed) => elapsed,
+        }
+    }
+
+    pub fn remaining(&self) -> Duration {
+        self.limit.saturating_sub(self.elapsed())
+    }
+
+    pub fn estimate(&self, cost: u32) -> Duration {
+        self.per_cost * cost
+    }
+
+    pub(super) fn tick(&mut self, cost: u32) {
+        let estimate = self.estimate(cost);
+        if let Source::Simulated(elapsed) = &mut self.source {
+            *elapsed += estimate;
+        }
+    }
+
+    pub(super) fn must_return(&self, home_cost: u32) -> bool {
+        self.remaining() <= self.estimate(home_cost) + self.margin
+    }
+}
@@ -0,0 +1,465 @@
+use super::{Direction, Kind, Maze, Position, Side, DIRECTIONS};
+use std::fmt;
+
+/// How often the side of an edge was scanned as a wall and as open, and what
+/// the last scan said. `contested` is set when the last scan went against the
+/// one before it, until the next check has dealt with it.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(super) struct Votes {
+    pub walls: u32,
+    pub opens: u32,
+    pub last: Side,
+    pub contested: bool,
+}
+
+/// What to do with the map when it contradicts itself.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Repair {
+    /// Leave the map alone and only report.
+    ReportOnly,
+    /// Go with the last scan of the side.
+    TrustLatest,
+    /// Go with what most scans of the side said, the last one on a tie.
+    TrustMajority,
+}
+
+/// Something the map says that can't be true.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Contradiction {
+    /// The last scan of the side of `pos` towards `direction` went against
+    /// the scan before it.
+    ConflictingScans {
+        pos: Position,
+        direction: Direction,
+        walls: u32,
+        opens: u32,
+    },
+    /// `pos` has a way through a side it has a wall on.
+    PathThroughWall { pos: Position, direction: Direction },
+    /// One of the two tiles has a wall between them and the other doesn't.
+    SidesDisagree {
+        pos: Position,
+        direction: Direction,
+        other: Position,
+    },
+    /// `pos` leads to `other`, but `other` doesn't lead back.
+    OneWayPath {
+        pos: Position,
+        direction: Direction,
+        other: Position,
+    },
+    /// A black tile that still leads somewhere.
+    BlackExit {
+        pos: Position,
+        direction: Direction,
+        other: Position,
+    },
+}
+
+impl fmt::Display for Contradiction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Contradiction::ConflictingScans {
+                pos,
+                direction,
+                walls,
+                opens,
+            } => write!(
+                f,
+                "{:?} {:?} scanned {} times as a wall and {} times as open",
+                pos, direction, walls, opens
+            ),
+            Contradiction::PathThroughWall { pos, direction } => {
+                write!(f, "{:?} has a path through its {:?} wall", pos, direction)
+            }
+            Contradiction::SidesDisagree {
+                pos,
+                direction,
+                other,
+            } => write!(
+                f,
+                "{:?} {:?} and {:?} disagree on the wall between them",
+                pos, direction, other
+            ),
+            Contradiction::OneWayPath {
+                pos,
+                direction,
+                other,
+            } => write!(
+                f,
+                "{:?} leads {:?} to {:?}, which doesn't lead back",
+                pos, direction, other
+            ),
+            Contradiction::BlackExit {
+                pos,
+                direction,
+                other,
+            } => write!(
+                f,
+                "black tile {:?} leads {:?} to {:?}",
+                pos, direction, other
+            ),
+        }
+    }
+}
+
+impl Maze {
+    pub fn set_repair(&mut self, repair: Repair) {
+        self.repair = repair;
+    }
+
+    /// Every contradiction the checks found, in the order they were found,
+    /// whether or not it was repaired.
+    pub fn contradictions(&self) -> &[Contradiction] {
+        &self.contradictions
+    }
+
+    pub(super) fn record_scan(&mut self, direction: Direction, side: Side) {
+        let key = self.edge_key(self.pos, direction);
+        let votes = self.scans.entry(key).or_insert(Votes {
+            walls: 0,
+            opens: 0,
+            last: side,
+            contested: false,
+        });
+        votes.contested |= votes.last != side;
+        match side {
+            Side::Wall => votes.walls += 1,
+            _ => votes.opens += 1,
+        }
+        votes.last = side;
+    }
+
+    /// Everything that doesn't add up in the map as it is now.
+    pub fn check_consistency(&self) -> Vec<Contradiction> {
+        let sides = self
+            .cells
+            .keys()
+            .flat_map(|pos| DIRECTIONS.map(|direction| (*pos, direction)))
+            .collect();
+        self.check_sides(sides)
+    }
+
+    // The sides of the tiles at `touched` and the sides of the tiles facing
+    // them, which is all a change to those tiles can have made go wrong.
+    fn sides_around(&self, touched: &[Position]) -> Vec<(Position, Direction)> {
+        let mut sides = vec![];
+        for pos in touched {
+            for direction in DIRECTIONS {
+                sides.push((*pos, direction));
+                let other = self.neighbor(*pos, direction);
+                if self.cells.contains_key(&other) {
+                    sides.push((other, direction.back()));
+                }
+            }
+        }
+        sides
+    }
+
+    fn check_sides(&self, mut sides: Vec<(Position, Direction)>) -> Vec<Contradiction> {
+        sides.sort();
+        sides.dedup();
+        let mut found = vec![];
+
+        // Both sides of an edge share its scans, kept under the lower one.
+        for (pos, direction) in &sides {
+            let key = self.edge_key(*pos, *direction);
+            let Some(votes) = self.scans.get(&key) else {
+                continue;
+            };
+            if key == (*pos, *direction) && votes.contested && self.cells.contains_key(pos) {
+                found.push(Contradiction::ConflictingScans {
+                    pos: *pos,
+                    direction: *direction,
+                    walls: votes.walls,
+                    opens: votes.opens,
+                });
+            }
+        }
+
+        for (pos, direction) in sides {
+            let Some(cell) = self.cells.get(&pos) else {
+                continue;
+            };
+            let side = cell.side(direction);
+            if let Some(other) = cell.neighbors.get(&direction).copied() {
+                if cell.kind == Kind::Black {
+                    found.push(Contradiction::BlackExit {
+                        pos,
+                        direction,
+                        other,
+                    });
+                    continue;
+                }
+                if side == Side::Wall {
+                    found.push(Contradiction::PathThroughWall { pos, direction });
+                }
+                // Nothing leads out of a black tile, so a path into one
+                // stays one way.
+                if let Some(far) = self.cells.get(&other) {
+                    if far.kind != Kind::Black && far.neighbors.get(&direction.back()) != Some(&pos)
+                    {
+                        found.push(Contradiction::OneWayPath {
+                            pos,
+                            direction,
+                            other,
+                        });
+                    }
+                }
+            }
+
+            // Only tiles that face each other share a side, which is not
+            // the case next to a ramp leading to another floor.
+            let other = self.neighbor(pos, direction);
+            if pos > other || self.neighbor(other, direction.back()) != pos {
+                continue;
+            }
+            let Some(far) = self.cells.get(&other) else {
+                continue;
+            };
+            let far_side = far.side(direction.back());
+            if side != Side::Unknown && far_side != Side::Unknown && side != far_side {
+                found.push(Contradiction::SidesDisagree {
+                    pos,
+                    direction,
+                    other,
+                });
+            }
+        }
+        found
+    }
+
+    /// Side the scans, or failing that the wall belief, agree on under the
+    /// repair policy. A wall if nothing is known, so the robot never drives
+    /// through a side it can't trust.
+    fn trusted_side(&self, pos: Position, direction: Direction) -> Side {
+        let key = self.edge_key(pos, direction);
+        if let Some(votes) = self.scans.get(&key) {
+            return match self.repair {
+                Repair::TrustMajority if votes.walls > votes.opens => Side::Wall,
+                Repair::TrustMajority if votes.opens > votes.walls => Side::Open,
+                _ => votes.last,
+            };
+        }
+        if self.wall_belief(pos, direction) < 0.0 {
+            Side::Open
+        } else {
+            Side::Wall
+        }
+    }
+
+    fn set_side(&mut self, pos: Position, direction: Direction, side: Side) {
+        let other = self.neighbor(pos, direction);
+        self.close_side(pos, direction);
+        if side == Side::Open {
+            self.link(pos, direction, other);
+        }
+    }
+
+    fn repair_one(&mut self, contradiction: Contradiction) {
+        match contradiction {
+            Contradiction::BlackExit { pos, direction, .. } => {
+                if let Some(cell) = self.cells.get_mut(&pos) {
+                    cell.neighbors.remove(&direction);
+                }
+            }
+            Contradiction::ConflictingScans { pos, direction, .. } => {
+                let side = self.trusted_side(pos, direction);
+                self.set_side(pos, direction, side);
+            }
+            Contradiction::PathThroughWall { pos, direction }
+            | Contradiction::SidesDisagree { pos, direction, .. }
+            | Contradiction::OneWayPath { pos, direction, .. } => {
+                let side = self.trusted_side(pos, direction);
+                self.set_side(pos, direction, side);
+            }
+        }
+    }
+
+    /// Runs after every change to the map with the tiles it touched: records
+    /// what is new around them and repairs it according to the policy. A
+    /// repair that cuts the planned path is caught by the path check before
+    /// the next move, so the path is kept otherwise.
+    pub(super) fn check_map(&mut self, touched: &[Position]) {
+        let sides = self.sides_around(touched);
+        let edges: Vec<_> = sides
+            .iter()
+            .map(|(pos, direction)| self.edge_key(*pos, *direction))
+            .collect();
+        let found = self.check_sides(sides);
+        if self.repair != Repair::ReportOnly {
+            for contradiction in &found {
+                self.repair_one(*contradiction);
+            }
+        }
+        for edge in edges {
+            if let Some(votes) = self.scans.get_mut(&edge) {
+                votes.contested = false;
+            }
+        }
+        // What the policy leaves in place is found again by the next check
+        // around it.
+        for contradiction in found {
+            if !self.contradictions.contains(&contradiction) {
+                self.contradictions.push(contradiction);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Cell;
+    use super::*;
+
+    const POLICIES: [Repair; 3] = [
+        Repair::ReportOnly,
+        Repair::TrustLatest,
+        Repair::TrustMajority,
+    ];
+    const START: Position = (0, 0, 0);
+    const AHEAD: Position = (0, -1, 0);
+
+    // The start tile with only the tile in front of it open, scanned once.
+    fn corridor(repair: Repair) -> Maze {
+        let mut maze = Maze::new();
+        maze.set_repair(repair);
+        maze.robot_scan(true, false, false, false);
+        maze
+    }
+
+    fn cell(maze: &mut Maze, pos: Position) -> &mut Cell {
+        maze.cells.get_mut(&pos).unwrap()
+    }
+
+    fn linked(maze: &Maze) -> (bool, bool) {
+        (
+            maze.cells[&START].neighbors.get(&Direction::Up) == Some(&AHEAD),
+            maze.cells[&AHEAD].neighbors.get(&Direction::Down) == Some(&START),
+        )
+    }
+
+    #[test]
+    fn conflicting_scans() {
+        for (repair, side) in POLICIES
+            .into_iter()
+            .zip([Side::Wall, Side::Wall, Side::Open])
+        {
+            let mut maze = corridor(repair);
+            maze.robot_scan(true, false, false, false);
+            maze.robot_scan(false, false, false, false);
+
+            // Kept under the lower side of the edge.
+            let expected = Contradiction::ConflictingScans {
+                pos: AHEAD,
+                direction: Direction::Down,
+                walls: 1,
+                opens: 2,
+            };
+            assert_eq!(maze.contradictions(), [expected], "{:?}", repair);
+            assert_eq!(maze.cells[&START].side(Direction::Up), side, "{:?}", repair);
+            assert_eq!(linked(&maze), (side == Side::Open, side == Side::Open));
+        }
+    }
+
+    #[test]
+    fn path_through_wall() {
+        for repair in POLICIES {
+            let mut maze = corridor(repair);
+            cell(&mut maze, START)
+                .walls
+                .insert(Direction::Up, Side::Wall);
+            maze.check_map(&[START]);
+
+            let expected = Contradiction::PathThroughWall {
+                pos: START,
+                direction: Direction::Up,
+            };
+            assert!(maze.contradictions().contains(&expected), "{:?}", repair);
+            let side = if repair == Repair::ReportOnly {
+                Side::Wall
+            } else {
+                Side::Open
+            };
+            assert_eq!(maze.cells[&START].side(Direction::Up), side, "{:?}", repair);
+            assert_eq!(linked(&maze), (true, true));
+        }
+    }
+
+    #[test]
+    fn sides_disagree() {
+        for repair in POLICIES {
+            let mut maze = corridor(repair);
+            cell(&mut maze, START).neighbors.remove(&Direction::Up);
+            let ahead = cell(&mut maze, AHEAD);
+            ahead.neighbors.remove(&Direction::Down);
+            ahead.walls.insert(Direction::Down, Side::Wall);
+            maze.check_map(&[START]);
+
+            let expected = Contradiction::SidesDisagree {
+                pos: AHEAD,
+                direction: Direction::Down,
+                other: START,
+            };
+            assert_eq!(maze.contradictions(), [expected], "{:?}", repair);
+            let repaired = repair != Repair::ReportOnly;
+            let side = if repaired { Side::Open } else { Side::Wall };
+            assert_eq!(maze.cells[&AHEAD].side(Direction::Down), side);
+            assert_eq!(linked(&maze), (repaired, repaired), "{:?}", repair);
+        }
+    }
+
+    #[test]
+    fn one_way_path() {
+        for repair in POLICIES {
+            let mut maze = corridor(repair);
+            cell(&mut maze, AHEAD).neighbors.remove(&Direction::Down);
+            maze.check_map(&[START]);
+
+            let expected = Contradiction::OneWayPath {
+                pos: START,
+                direction: Direction::Up,
+                other: AHEAD,
+            };
+            assert_eq!(maze.contradictions(), [expected], "{:?}", repair);
+            let repaired = repair != Repair::ReportOnly;
+            assert_eq!(linked(&maze), (true, repaired), "{:?}", repair);
+        }
+    }
+
+    #[test]
+    fn black_exit() {
+        for repair in POLICIES {
+            let mut maze = corridor(repair);
+            cell(&mut maze, AHEAD).kind = Kind::Black;
+            maze.check_map(&[START]);
+
+            let expected = Contradiction::BlackExit {
+                pos: AHEAD,
+                direction: Direction::Down,
+                other: START,
+            };
+            assert_eq!(maze.contradictions(), [expected], "{:?}", repair);
+            let repaired = repair != Repair::ReportOnly;
+            assert_eq!(linked(&maze), (true, !repaired), "{:?}", repair);
+        }
+    }
+
+    #[test]
+    fn only_the_touched_tiles_are_checked() {
+        let mut maze = corridor(Repair::TrustLatest);
+        maze.path = vec![AHEAD];
+        cell(&mut maze, AHEAD).neighbors.remove(&Direction::Down);
+
+        // Two tiles away from the break.
+        maze.check_map(&[(0, 1, 0)]);
+        assert!(maze.contradictions().is_empty());
+        assert_eq!(maze.check_consistency().len(), 1);
+
+        // The repair doesn't cut the path, so it is kept.
+        maze.check_map(&[AHEAD]);
+        assert_eq!(maze.contradictions().len(), 1);
+        assert_eq!(maze.path, [AHEAD]);
+        assert!(maze.check_consistency().is_empty());
+    }
+}
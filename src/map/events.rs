@@ -0,0 +1,493 @@
+use super::save::{
+    direction_name, kind_name, parse_direction, parse_kind, parse_obstacle, parse_position,
+    parse_side, side_name,
+};
+use super::{Direction, Kind, Maze, Obstacle, Position, Side, VictimKind};
+use anyhow::{anyhow, bail, Result};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+
+/// Something that happened to the map, in enough detail to play the run
+/// back. Positions and directions are absolute, not relative to the robot.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Event {
+    /// The robot on `pos` facing `dir` saw these sides open or walled, in
+    /// front, right, left, back order.
+    Scan {
+        pos: Position,
+        dir: Direction,
+        sides: [Side; 4],
+    },
+    /// Raw distances read on `pos` facing `dir`, in the same order.
+    Ranges {
+        pos: Position,
+        dir: Direction,
+        ranges: [Option<u8>; 4],
+    },
+    /// The robot is now on `pos` facing `dir`.
+    Move {
+        pos: Position,
+        dir: Direction,
+    },
+    /// Tiles the robot is going to drive through.
+    Path(Vec<Position>),
+    /// A path that can't be driven from where the robot is, either the one
+    /// it was following or one it was about to take, so it was dropped.
+    Blocked(Vec<Position>),
+    /// `pos` turned out to be a blue or black tile.
+    Mark {
+        pos: Position,
+        kind: Kind,
+    },
+    /// The robot drove onto a ramp on `pos` facing `dir`.
+    Ramp {
+        pos: Position,
+        dir: Direction,
+        up: bool,
+    },
+    Checkpoint(Position),
+    /// An obstacle was seen on `pos`, or it was found clear if none of its
+    /// quarters is blocked.
+    Obstacle {
+        pos: Position,
+        obstacle: Obstacle,
+    },
+    Victim {
+        pos: Position,
+        side: Direction,
+        kind: VictimKind,
+    },
+    LackOfProgress,
+    /// The mission clock ran low and the robot headed back to the start.
+    Return,
+}
+
+fn write_position(f: &mut fmt::Formatter, (x, y, level): Position) -> fmt::Result {
+    write!(f, " {} {} {}", x, y, level)
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Event::Scan { pos, dir, sides } => {
+                write!(f, "scan")?;
+                write_position(f, *pos)?;
+                write!(f, " {}", direction_name(*dir))?;
+                for side in sides {
+                    write!(f, " {}", side_name(*side))?;
+                }
+                Ok(())
+            }
+            Event::Ranges { pos, dir, ranges } => {
+                write!(f, "ranges")?;
+                write_position(f, *pos)?;
+                write!(f, " {}", direction_name(*dir))?;
+                for range in ranges {
+                    match range {
+                        Some(range) => write!(f, " {}", range)?,
+                        None => write!(f, " -")?,
+                    }
+                }
+                Ok(())
+            }
+            Event::Move { pos, dir } => {
+                write!(f, "move")?;
+                write_position(f, *pos)?;
+                write!(f, " {}", direction_name(*dir))
+            }
+            Event::Path(path) => {
+                write!(f, "path")?;
+                for pos in path {
+                    write_position(f, *pos)?;
+                }
+                Ok(())
+            }
+            Event::Blocked(path) => {
+                write!(f, "blocked")?;
+                for pos in path {
+                    write_position(f, *pos)?;
+                }
+                Ok(())
+            }
+            Event::Mark { pos, kind } => {
+                write!(f, "mark")?;
+                write_position(f, *pos)?;
+                write!(f, " {}", kind_name(*kind))
+            }
+            Event::Ramp { pos, dir, up } => {
+                write!(f, "ramp")?;
+                write_position(f, *pos)?;
+                let way = if *up { "up" } else { "down" };
+                write!(f, " {} {}", direction_name(*dir), way)
+            }
+            Event::Checkpoint(pos) => {
+                write!(f, "checkpoint")?;
+                write_position(f, *pos)
+            }
+            Event::Obstacle { pos, obstacle } => {
+                write!(f, "obstacle")?;
+                write_position(f, *pos)?;
+                write!(f, " {}", obstacle.bits())
+            }
+            Event::Victim { pos, side, kind } => {
+                write!(f, "victim")?;
+                write_position(f, *pos)?;
+                write!(f, " {} {}", direction_name(*side), kind.label())
+            }
+            Event::LackOfProgress => write!(f, "lack_of_progress"),
+            Event::Return => write!(f, "return"),
+        }
+    }
+}
+
+impl Event {
+    /// Reads back a line written by the [`fmt::Display`] of an event.
+    pub fn parse(line: &str) -> Result<Event> {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        let event = match tokens.as_slice() {
+            ["scan", x, y, level, dir, sides @ ..] if sides.len() == 4 => Event::Scan {
+                pos: parse_position(&[x, y, level])?,
+                dir: parse_direction(dir)?,
+                sides: [
+                    parse_side(sides[0])?,
+                    parse_side(sides[1])?,
+                    parse_side(sides[2])?,
+                    parse_side(sides[3])?,
+                ],
+            },
+            ["ranges", x, y, level, dir, ranges @ ..] if ranges.len() == 4 => {
+                let mut parsed = [None; 4];
+                for (range, token) in parsed.iter_mut().zip(ranges) {
+                    if *token != "-" {
+                        *range = Some(token.parse()?);
+                    }
+                }
+                Event::Ranges {
+                    pos: parse_position(&[x, y, level])?,
+                    dir: parse_direction(dir)?,
+                    ranges: parsed,
+                }
+            }
+            ["move", x, y, level, dir] => Event::Move {
+                pos: parse_position(&[x, y, level])?,
+                dir: parse_direction(dir)?,
+            },
+            ["path", rest @ ..] => Event::Path(
+                rest.chunks(3)
+                    .map(parse_position)
+                    .collect::<Result<Vec<_>>>()?,
+            ),
+            ["blocked", rest @ ..] => Event::Blocked(
+                rest.chunks(3)
+                    .map(parse_position)
+                    .collect::<Result<Vec<_>>>()?,
+            ),
+            ["mark", x, y, level, kind] => Event::Mark {
+                pos: parse_position(&[x, y, level])?,
+                kind: parse_kind(kind)?,
+            },
+            ["ramp", x, y, level, dir, way] => Event::Ramp {
+                pos: parse_position(&[x, y, level])?,
+                dir: parse_direction(dir)?,
+                up: match *way {
+                    "up" => true,
+                    "down" => false,
+                    _ => bail!("Invalid ramp direction: {}", way),
+                },
+            },
+            ["checkpoint", x, y, level] => Event::Checkpoint(parse_position(&[x, y, level])?),
+            ["obstacle", x, y, level, bits] => Event::Obstacle {
+                pos: parse_position(&[x, y, level])?,
+                obstacle: parse_obstacle(bits)?,
+            },
+            ["victim", x, y, level, side, kind] => Event::Victim {
+                pos: parse_position(&[x, y, level])?,
+                side: parse_direction(side)?,
+                kind: VictimKind::from_label(kind)
+                    .ok_or_else(|| anyhow!("Invalid victim: {}", kind))?,
+            },
+            ["lack_of_progress"] => Event::LackOfProgress,
+            ["return"] => Event::Return,
+            _ => bail!("Unexpected event: {}", line),
+        };
+        Ok(event)
+    }
+}
+
+impl Maze {
+    /// Writes every event to `path` as it happens, one per line, on top of
+    /// keeping them in [`Maze::events`]. Nothing is kept before this is
+    /// called.
+    pub fn record_events(&mut self, path: impl AsRef<Path>) -> Result<()> {
+        self.event_file = Some(File::create(path)?);
+        self.event_error = None;
+        Ok(())
+    }
+
+    /// Stops writing events. Returns the error that stopped the recording
+    /// early, if writing an event failed.
+    pub fn stop_recording(&mut self) -> Result<()> {
+        self.event_file = None;
+        match self.event_error.take() {
+            Some(err) => Err(anyhow!("Error writing event: {}", err)),
+            None => Ok(()),
+        }
+    }
+
+    /// Events since the recording started, or played back so far.
+    pub fn events(&self) -> &[Event] {
+        &self.events
+    }
+
+    pub(super) fn emit(&mut self, event: Event) {
+        if self.replaying {
+            return;
+        }
+        let Some(file) = &mut self.event_file else {
+            return;
+        };
+        // The rest of the run can't be played back without this event, so
+        // the recording stops there.
+        if let Err(err) = writeln!(file, "{}", event) {
+            self.event_file = None;
+            self.event_error = Some(err);
+            return;
+        }
+        self.events.push(event);
+    }
+
+    /// Does to the map what `event` says happened, without recording it
+    /// again.
+    fn apply(&mut self, event: &Event) {
+        self.events.push(event.clone());
+        match event {
+            Event::Scan { pos, dir, sides } => {
+                self.pos = *pos;
+                self.dir = *dir;
+                let open = sides.map(|side| side == Side::Open);
+                self.robot_scan(open[0], open[1], open[2], open[3]);
+            }
+            Event::Ranges { pos, dir, ranges } => {
+                self.pos = *pos;
+                self.dir = *dir;
+                self.robot_ranges(ranges[0], ranges[1], ranges[2], ranges[3]);
+            }
+            Event::Move { pos, dir } => {
+                self.pos = *pos;
+                self.dir = *dir;
+                if let Some(cell) = self.cells.get_mut(pos) {
+                    if cell.kind == Kind::Unknown {
+                        cell.kind = Kind::Empty;
+                    }
+                }
+                if let Some(index) = self.path.iter().position(|step| step == pos) {
+                    self.path.drain(..=index);
+                }
+            }
+            Event::Path(path) => self.path = path.clone(),
+            Event::Blocked(_) => self.path.clear(),
+            Event::Mark { pos, kind } => {
+                self.pos = *pos;
+                match kind {
+                    Kind::Black => self.add_black(),
+                    Kind::Blue => self.add_blue(),
+                    _ => {
+                        if let Some(cell) = self.cells.get_mut(pos) {
+                            cell.kind = *kind;
+                        }
+                    }
+                }
+            }
+            Event::Ramp { pos, dir, up } => {
+                self.pos = *pos;
+                self.dir = *dir;
+                self.add_ramp(*up);
+            }
+            Event::Checkpoint(pos) => {
+                self.pos = *pos;
+                self.add_checkpoint();
+            }
+            Event::Obstacle { pos, obstacle } => self.set_obstacle(*pos, *obstacle),
+            Event::Victim { pos, side, kind } => {
+                let dir = self.dir;
+                self.pos = *pos;
+                self.dir = Direction::Up;
+                self.add_victim(*kind, *side);
+                self.dir = dir;
+            }
+            Event::LackOfProgress => self.lack_of_progress(),
+            Event::Return => {
+                self.returning = true;
+                self.path.clear();
+            }
+        }
+    }
+}
+
+/// Plays back an event file written by [`Maze::record_events`], drawing the
+/// map after every event. Reads commands from stdin: enter or `n` for the
+/// next event, `p` for the previous one, a number to jump to that event and
+/// `q` to stop.
+pub fn replay(path: impl AsRef<Path>) -> Result<()> {
+    let text = fs::read_to_string(path)?;
+    let events = text
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(number, line)| {
+            Event::parse(line).map_err(|err| anyhow!("Line {}: {}", number + 1, err))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut maze = Maze::new();
+    maze.replaying = true;
+    let mut frames = vec![maze.render()];
+    for event in &events {
+        maze.apply(event);
+        frames.push(maze.render());
+    }
+
+    let mut step = 0;
+    let stdin = io::stdin();
+    loop {
+        // Clears the terminal before drawing.
+        print!("\x1B[2J\x1B[H");
+        match step {
+            0 => println!("start (0/{})", events.len()),
+            _ => println!("{} ({}/{})", events[step - 1], step, events.len()),
+        }
+        print!("{}", frames[step]);
+        print!("[n]ext, [p]revious, <number>, [q]uit: ");
+        io::stdout().flush()?;
+
+        let mut input = String::new();
+        if stdin.lock().read_line(&mut input)? == 0 {
+            return Ok(());
+        }
+        match input.trim() {
+            "" | "n" => step = (step + 1).min(events.len()),
+            "p" => step = step.saturating_sub(1),
+            "q" => return Ok(()),
+            number => match number.parse::<usize>() {
+                Ok(number) => step = number.min(events.len()),
+                Err(_) => println!("Unknown command: {}", number),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Quarter;
+    use super::*;
+
+    // Fails to build when an event is added, so it gets a case below.
+    fn name(event: &Event) -> &'static str {
+        match event {
+            Event::Scan { .. } => "scan",
+            Event::Ranges { .. } => "ranges",
+            Event::Move { .. } => "move",
+            Event::Path(_) => "path",
+            Event::Blocked(_) => "blocked",
+            Event::Mark { .. } => "mark",
+            Event::Ramp { .. } => "ramp",
+            Event::Checkpoint(_) => "checkpoint",
+            Event::Obstacle { .. } => "obstacle",
+            Event::Victim { .. } => "victim",
+            Event::LackOfProgress => "lack_of_progress",
+            Event::Return => "return",
+        }
+    }
+
+    #[test]
+    fn every_event_reads_back() {
+        let events = [
+            Event::Scan {
+                pos: (1, -2, 0),
+                dir: Direction::Left,
+                sides: [Side::Open, Side::Wall, Side::Unknown, Side::Wall],
+            },
+            Event::Ranges {
+                pos: (0, 0, 1),
+                dir: Direction::Down,
+                ranges: [Some(40), None, Some(0), Some(255)],
+            },
+            Event::Move {
+                pos: (-3, 4, -1),
+                dir: Direction::Right,
+            },
+            Event::Path(vec![(0, 1, 0), (0, 2, 0), (1, 2, 1)]),
+            Event::Path(vec![]),
+            Event::Blocked(vec![(3, 0, 0)]),
+            Event::Mark {
+                pos: (2, 2, 0),
+                kind: Kind::Black,
+            },
+            Event::Ramp {
+                pos: (1, 0, 0),
+                dir: Direction::Up,
+                up: true,
+            },
+            Event::Ramp {
+                pos: (1, 0, 1),
+                dir: Direction::Down,
+                up: false,
+            },
+            Event::Checkpoint((5, -5, 2)),
+            Event::Obstacle {
+                pos: (0, 3, 0),
+                obstacle: Obstacle::new(&[Quarter::UpRight, Quarter::DownLeft]),
+            },
+            Event::Obstacle {
+                pos: (0, 3, 0),
+                obstacle: Obstacle::default(),
+            },
+            Event::Victim {
+                pos: (1, 1, 0),
+                side: Direction::Right,
+                kind: VictimKind::Yellow,
+            },
+            Event::LackOfProgress,
+            Event::Return,
+        ];
+        let mut names: Vec<&str> = events.iter().map(name).collect();
+        names.dedup();
+        assert_eq!(names.len(), 12);
+
+        for event in events {
+            assert_eq!(Event::parse(&event.to_string()).unwrap(), event);
+        }
+    }
+
+    #[test]
+    fn events_are_only_kept_while_recording() {
+        let mut maze = Maze::new();
+        maze.robot_scan(true, false, false, false);
+        assert!(maze.events().is_empty());
+
+        let path = std::env::temp_dir().join(format!("{}-events.txt", std::process::id()));
+        maze.record_events(&path).unwrap();
+        maze.robot_scan(true, false, false, false);
+        maze.stop_recording().unwrap();
+        maze.robot_scan(true, false, false, false);
+
+        let text = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(maze.events().len(), 1);
+        assert_eq!(text, format!("{}\n", maze.events()[0]));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn write_errors_stop_the_recording() {
+        let mut maze = Maze::new();
+        maze.record_events("/dev/full").unwrap();
+        maze.robot_scan(true, false, false, false);
+        maze.robot_scan(true, false, false, false);
+        assert!(maze.events().is_empty());
+        assert!(maze.stop_recording().is_err());
+        assert!(maze.stop_recording().is_ok());
+    }
+}
@@ -0,0 +1,385 @@
+use super::{Direction, Kind, Maze, Position, Quarter, Side, VictimKind, DIRECTIONS};
+use anyhow::{bail, Result};
+use opencv::{
+    core::{Mat, Point, Rect, Scalar, Vector, CV_8UC3},
+    imgcodecs,
+    imgproc::{self, FILLED, FONT_HERSHEY_SIMPLEX, LINE_AA},
+};
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Side of a tile in the exported images, in pixels.
+const TILE: i32 = 40;
+const MARGIN: i32 = 20;
+
+type Color = (u8, u8, u8);
+
+const WHITE: Color = (255, 255, 255);
+const INK: Color = (20, 20, 20);
+const UNKNOWN_WALL: Color = (190, 190, 190);
+const PATH: Color = (200, 0, 200);
+const ROBOT: Color = (220, 60, 20);
+const OBSTACLE: Color = (120, 90, 60);
+
+fn kind_color(kind: Kind) -> Color {
+    match kind {
+        Kind::Start => (150, 230, 150),
+        Kind::Unknown => (225, 225, 225),
+        Kind::Empty => WHITE,
+        Kind::Checkpoint => (185, 185, 200),
+        Kind::Blue => (90, 140, 235),
+        Kind::Ramp => (215, 180, 130),
+        Kind::Black => (40, 40, 40),
+    }
+}
+
+fn kind_label(kind: Kind) -> Option<&'static str> {
+    match kind {
+        Kind::Start => Some("S"),
+        Kind::Checkpoint => Some("C"),
+        Kind::Ramp => Some("R"),
+        _ => None,
+    }
+}
+
+fn victim_color(kind: VictimKind) -> Color {
+    match kind {
+        VictimKind::Red => (220, 30, 50),
+        VictimKind::Yellow => (245, 210, 0),
+        VictimKind::Green => (30, 150, 50),
+        VictimKind::H | VictimKind::S | VictimKind::U => WHITE,
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum ImageFormat {
+    Svg,
+    Png,
+}
+
+impl ImageFormat {
+    fn from_path(path: &Path) -> Option<ImageFormat> {
+        match path.extension()?.to_str()? {
+            "svg" => Some(ImageFormat::Svg),
+            "png" => Some(ImageFormat::Png),
+            _ => None,
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            ImageFormat::Svg => "svg",
+            ImageFormat::Png => "png",
+        }
+    }
+}
+
+// What drawing a floor takes, so that both formats come out of the same code.
+trait Canvas {
+    fn rect(&mut self, at: (i32, i32), size: (i32, i32), fill: Color) -> Result<()>;
+    fn line(&mut self, from: (i32, i32), to: (i32, i32), color: Color, width: i32) -> Result<()>;
+    fn circle(&mut self, center: (i32, i32), radius: i32, fill: Color) -> Result<()>;
+    /// Writes `text` centered on `at`.
+    fn text(&mut self, at: (i32, i32), text: &str, color: Color) -> Result<()>;
+}
+
+struct Svg(String);
+
+impl Svg {
+    fn new((width, height): (i32, i32)) -> Self {
+        Svg(format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{0}\" height=\"{1}\" viewBox=\"0 0 {0} {1}\">\n",
+            width, height
+        ))
+    }
+
+    fn finish(mut self) -> String {
+        self.0.push_str("</svg>\n");
+        self.0
+    }
+}
+
+fn rgb((r, g, b): Color) -> String {
+    format!("rgb({},{},{})", r, g, b)
+}
+
+impl Canvas for Svg {
+    fn rect(&mut self, (x, y): (i32, i32), (width, height): (i32, i32), fill: Color) -> Result<()> {
+        writeln!(
+            self.0,
+            "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"{}\"/>",
+            x,
+            y,
+            width,
+            height,
+            rgb(fill)
+        )?;
+        Ok(())
+    }
+
+    fn line(&mut self, from: (i32, i32), to: (i32, i32), color: Color, width: i32) -> Result<()> {
+        writeln!(
+            self.0,
+            "<line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\" stroke=\"{}\" stroke-width=\"{}\" stroke-linecap=\"round\"/>",
+            from.0,
+            from.1,
+            to.0,
+            to.1,
+            rgb(color),
+            width
+        )?;
+        Ok(())
+    }
+
+    fn circle(&mut self, (x, y): (i32, i32), radius: i32, fill: Color) -> Result<()> {
+        writeln!(
+            self.0,
+            "<circle cx=\"{}\" cy=\"{}\" r=\"{}\" fill=\"{}\"/>",
+            x,
+            y,
+            radius,
+            rgb(fill)
+        )?;
+        Ok(())
+    }
+
+    fn text(&mut self, (x, y): (i32, i32), text: &str, color: Color) -> Result<()> {
+        writeln!(
+            self.0,
+            "<text x=\"{}\" y=\"{}\" fill=\"{}\" font-family=\"sans-serif\" font-size=\"12\" text-anchor=\"middle\" dominant-baseline=\"central\">{}</text>",
+            x,
+            y,
+            rgb(color),
+            text
+        )?;
+        Ok(())
+    }
+}
+
+struct Png(Mat);
+
+// OpenCV takes colors in BGR order.
+fn scalar((r, g, b): Color) -> Scalar {
+    Scalar::new(b as f64, g as f64, r as f64, 0.0)
+}
+
+fn point((x, y): (i32, i32)) -> Point {
+    Point::new(x, y)
+}
+
+impl Png {
+    fn new((width, height): (i32, i32)) -> Result<Self> {
+        Ok(Png(Mat::new_rows_cols_with_default(
+            height,
+            width,
+            CV_8UC3,
+            scalar(WHITE),
+        )?))
+    }
+}
+
+impl Canvas for Png {
+    fn rect(&mut self, (x, y): (i32, i32), (width, height): (i32, i32), fill: Color) -> Result<()> {
+        let rect = Rect::new(x, y, width, height);
+        imgproc::rectangle(&mut self.0, rect, scalar(fill), FILLED, LINE_AA, 0)?;
+        Ok(())
+    }
+
+    fn line(&mut self, from: (i32, i32), to: (i32, i32), color: Color, width: i32) -> Result<()> {
+        let (from, to) = (point(from), point(to));
+        imgproc::line(&mut self.0, from, to, scalar(color), width, LINE_AA, 0)?;
+        Ok(())
+    }
+
+    fn circle(&mut self, center: (i32, i32), radius: i32, fill: Color) -> Result<()> {
+        let center = point(center);
+        imgproc::circle(
+            &mut self.0,
+            center,
+            radius,
+            scalar(fill),
+            FILLED,
+            LINE_AA,
+            0,
+        )?;
+        Ok(())
+    }
+
+    fn text(&mut self, (x, y): (i32, i32), text: &str, color: Color) -> Result<()> {
+        let (font, size) = (FONT_HERSHEY_SIMPLEX, 0.4);
+        let mut baseline = 0;
+        let bounds = imgproc::get_text_size(text, font, size, 1, &mut baseline)?;
+        let origin = Point::new(x - bounds.width / 2, y + bounds.height / 2);
+        imgproc::put_text(
+            &mut self.0,
+            text,
+            origin,
+            font,
+            size,
+            scalar(color),
+            1,
+            LINE_AA,
+            false,
+        )?;
+        Ok(())
+    }
+}
+
+// Unit step of `direction` in image coordinates, where up is -y as on the map.
+fn offset(direction: Direction) -> (i32, i32) {
+    let (x, y, _) = direction.step((0, 0, 0));
+    (x, y)
+}
+
+impl Maze {
+    // Top left tile of `level` and the size of the image drawn for it.
+    fn image_bounds(&self, level: i32) -> ((i32, i32), (i32, i32)) {
+        let tiles = self.cells.keys().filter(|pos| pos.2 == level);
+        let (mut min_x, mut max_x) = (i32::MAX, i32::MIN);
+        let (mut min_y, mut max_y) = (i32::MAX, i32::MIN);
+        for (x, y, _) in tiles {
+            min_x = min_x.min(*x);
+            max_x = max_x.max(*x);
+            min_y = min_y.min(*y);
+            max_y = max_y.max(*y);
+        }
+        let size = (
+            (max_x - min_x + 1) * TILE + 2 * MARGIN,
+            (max_y - min_y + 1) * TILE + 2 * MARGIN,
+        );
+        ((min_x, min_y), size)
+    }
+
+    fn draw_level(&self, level: i32, canvas: &mut impl Canvas) -> Result<()> {
+        let ((min_x, min_y), size) = self.image_bounds(level);
+        let corner =
+            |(x, y, _): Position| (MARGIN + (x - min_x) * TILE, MARGIN + (y - min_y) * TILE);
+        let center = |pos: Position| {
+            let (x, y) = corner(pos);
+            (x + TILE / 2, y + TILE / 2)
+        };
+        canvas.rect((0, 0), size, WHITE)?;
+
+        let tiles: Vec<_> = self.tiles().filter(|tile| tile.pos.2 == level).collect();
+        for tile in &tiles {
+            canvas.rect(corner(tile.pos), (TILE, TILE), kind_color(tile.kind))?;
+            if let Some(label) = kind_label(tile.kind) {
+                canvas.text(center(tile.pos), label, INK)?;
+            }
+        }
+
+        let half = TILE / 2;
+        for tile in &tiles {
+            let Some(obstacle) = tile.obstacle else {
+                continue;
+            };
+            let (x, y) = corner(tile.pos);
+            for quarter in obstacle.quarters() {
+                let at = match quarter {
+                    Quarter::UpLeft => (x, y),
+                    Quarter::UpRight => (x + half, y),
+                    Quarter::DownRight => (x + half, y + half),
+                    Quarter::DownLeft => (x, y + half),
+                };
+                canvas.rect(at, (half, half), OBSTACLE)?;
+            }
+        }
+
+        // Unknown sides first, so that walls are drawn over them.
+        for wanted in [Side::Unknown, Side::Wall] {
+            for tile in &tiles {
+                let (x, y) = corner(tile.pos);
+                for direction in DIRECTIONS {
+                    if tile.side(direction) != wanted {
+                        continue;
+                    }
+                    let (from, to) = match direction {
+                        Direction::Up => ((x, y), (x + TILE, y)),
+                        Direction::Down => ((x, y + TILE), (x + TILE, y + TILE)),
+                        Direction::Left => ((x, y), (x, y + TILE)),
+                        Direction::Right => ((x + TILE, y), (x + TILE, y + TILE)),
+                    };
+                    match wanted {
+                        Side::Wall => canvas.line(from, to, INK, 4)?,
+                        _ => canvas.line(from, to, UNKNOWN_WALL, 1)?,
+                    }
+                }
+            }
+        }
+
+        // Only the steps that stay on this floor.
+        let (pos, dir) = self.pose();
+        let path = std::iter::once(&pos).chain(self.planned_path());
+        for (from, to) in path.clone().zip(path.skip(1)) {
+            if from.2 == level && to.2 == level {
+                canvas.line(center(*from), center(*to), PATH, 3)?;
+            }
+        }
+
+        for (pos, side, victim) in self.victims() {
+            if pos.2 != level {
+                continue;
+            }
+            let (x, y) = center(pos);
+            let (dx, dy) = offset(side);
+            let at = (x + dx * (TILE / 2 - 11), y + dy * (TILE / 2 - 11));
+            canvas.circle(at, 10, INK)?;
+            canvas.circle(at, 9, victim_color(victim.kind))?;
+            if matches!(victim.kind, VictimKind::H | VictimKind::S | VictimKind::U) {
+                canvas.text(at, victim.kind.label(), INK)?;
+            }
+        }
+
+        if pos.2 == level {
+            let (x, y) = center(pos);
+            let (dx, dy) = offset(dir);
+            canvas.circle((x, y), 9, ROBOT)?;
+            canvas.line((x, y), (x + dx * 14, y + dy * 14), ROBOT, 4)?;
+        }
+        Ok(())
+    }
+
+    /// Floor `level` of the map as an SVG image.
+    pub fn svg(&self, level: i32) -> String {
+        let (_, size) = self.image_bounds(level);
+        let mut svg = Svg::new(size);
+        // Writing to a String can't fail.
+        self.draw_level(level, &mut svg).unwrap();
+        svg.finish()
+    }
+
+    /// Draws the map with walls, tile kinds, victims, the robot and its
+    /// planned path, one image per floor. `map.svg` is written as `map-0.svg`,
+    /// `map-1.svg` and so on, and the extension picks SVG or PNG. Returns the
+    /// files written.
+    pub fn export_images(&self, path: impl AsRef<Path>) -> Result<Vec<PathBuf>> {
+        let path = path.as_ref();
+        let Some(format) = ImageFormat::from_path(path) else {
+            bail!("Unknown image format: {}, use .svg or .png", path.display());
+        };
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+
+        let mut levels: Vec<i32> = self.cells.keys().map(|pos| pos.2).collect();
+        levels.sort();
+        levels.dedup();
+        let mut written = vec![];
+        for level in levels {
+            let file = path.with_file_name(format!("{}-{}.{}", stem, level, format.extension()));
+            match format {
+                ImageFormat::Svg => fs::write(&file, self.svg(level))?,
+                ImageFormat::Png => {
+                    let (_, size) = self.image_bounds(level);
+                    let mut png = Png::new(size)?;
+                    self.draw_level(level, &mut png)?;
+                    let name = file.to_string_lossy();
+                    if !imgcodecs::imwrite(&name, &png.0, &Vector::new())? {
+                        bail!("Could not write {}", name);
+                    }
+                }
+            }
+            written.push(file);
+        }
+        Ok(written)
+    }
+}
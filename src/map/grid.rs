@@ -0,0 +1,231 @@
+use super::{Cell, Position};
+use std::collections::HashMap;
+use std::ops::Index;
+
+/// Index of a tile in the [`Grid`]. It never changes, and isn't given to
+/// another tile once its own is removed.
+pub(super) type TileId = usize;
+
+// One floor: a dense rectangle of slots that grows in any direction by
+// copying into a bigger rectangle, so positions themselves never move.
+#[derive(Clone)]
+struct Layer {
+    min_x: i32,
+    min_y: i32,
+    width: i32,
+    height: i32,
+    slots: Vec<Option<TileId>>,
+}
+
+impl Layer {
+    fn new(x: i32, y: i32) -> Self {
+        Layer {
+            min_x: x,
+            min_y: y,
+            width: 1,
+            height: 1,
+            slots: vec![None],
+        }
+    }
+
+    fn index(&self, x: i32, y: i32) -> Option<usize> {
+        let (dx, dy) = (x - self.min_x, y - self.min_y);
+        if dx < 0 || dy < 0 || dx >= self.width || dy >= self.height {
+            return None;
+        }
+        Some((dy * self.width + dx) as usize)
+    }
+
+    // At least doubles the side that has to grow, so a run of new tiles
+    // along one direction only copies the layer a few times.
+    fn grow(&mut self, x: i32, y: i32) {
+        let (mut min_x, mut max_x) = (self.min_x, self.min_x + self.width - 1);
+        let (mut min_y, mut max_y) = (self.min_y, self.min_y + self.height - 1);
+        if x < min_x {
+            min_x = x.min(min_x - self.width);
+        } else if x > max_x {
+            max_x = x.max(max_x + self.width);
+        }
+        if y < min_y {
+            min_y = y.min(min_y - self.height);
+        } else if y > max_y {
+            max_y = y.max(max_y + self.height);
+        }
+
+        let mut grown = Layer {
+            min_x,
+            min_y,
+            width: max_x - min_x + 1,
+            height: max_y - min_y + 1,
+            slots: vec![],
+        };
+        grown.slots = vec![None; (grown.width * grown.height) as usize];
+        for dy in 0..self.height {
+            for dx in 0..self.width {
+                let slot = self.slots[(dy * self.width + dx) as usize];
+                let index = grown.index(self.min_x + dx, self.min_y + dy).unwrap();
+                grown.slots[index] = slot;
+            }
+        }
+        *self = grown;
+    }
+}
+
+/// Storage for the tiles of the maze, on every floor. Lookups by position
+/// are a bounds check and an index, and tiles keep their [`TileId`] and
+/// their position however much the grid grows around them.
+#[derive(Clone, Default)]
+pub(super) struct Grid {
+    // A removed tile leaves a `None` behind, so the ids after it hold.
+    tiles: Vec<Option<Cell>>,
+    levels: HashMap<i32, Layer>,
+    len: usize,
+}
+
+impl Grid {
+    pub fn id(&self, pos: &Position) -> Option<TileId> {
+        let layer = self.levels.get(&pos.2)?;
+        layer.slots[layer.index(pos.0, pos.1)?]
+    }
+
+    pub fn tile(&self, id: TileId) -> &Cell {
+        self.tiles[id].as_ref().expect("Tile was removed")
+    }
+
+    pub fn get(&self, pos: &Position) -> Option<&Cell> {
+        self.id(pos).and_then(|id| self.tiles[id].as_ref())
+    }
+
+    pub fn get_mut(&mut self, pos: &Position) -> Option<&mut Cell> {
+        self.id(pos).and_then(|id| self.tiles[id].as_mut())
+    }
+
+    pub fn contains_key(&self, pos: &Position) -> bool {
+        self.id(pos).is_some()
+    }
+
+    /// Adds a tile, or replaces the one already at `pos` keeping its id.
+    pub fn insert(&mut self, pos: Position, mut cell: Cell) -> TileId {
+        cell.pos = pos;
+        if let Some(id) = self.id(&pos) {
+            self.tiles[id] = Some(cell);
+            return id;
+        }
+
+        let layer = self
+            .levels
+            .entry(pos.2)
+            .or_insert_with(|| Layer::new(pos.0, pos.1));
+        if layer.index(pos.0, pos.1).is_none() {
+            layer.grow(pos.0, pos.1);
+        }
+        let id = self.tiles.len();
+        let index = layer.index(pos.0, pos.1).unwrap();
+        layer.slots[index] = Some(id);
+        self.tiles.push(Some(cell));
+        self.len += 1;
+        id
+    }
+
+    /// Takes the tile at `pos` out. Every other tile keeps its id.
+    pub fn remove(&mut self, pos: &Position) -> Option<Cell> {
+        let id = self.id(pos)?;
+        let layer = self.levels.get_mut(&pos.2).unwrap();
+        let index = layer.index(pos.0, pos.1).unwrap();
+        layer.slots[index] = None;
+        self.len -= 1;
+        self.tiles[id].take()
+    }
+
+    /// Tiles in the order they were added.
+    pub fn iter(&self) -> impl Iterator<Item = (&Position, &Cell)> {
+        self.values().map(|cell| (&cell.pos, cell))
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &Position> {
+        self.values().map(|cell| &cell.pos)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &Cell> {
+        self.tiles.iter().flatten()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl Index<&Position> for Grid {
+    type Output = Cell;
+
+    fn index(&self, pos: &Position) -> &Cell {
+        self.get(pos).expect("No tile at this position")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Kind;
+    use super::*;
+
+    #[test]
+    fn grows_towards_negative_coordinates() {
+        let mut grid = Grid::default();
+        let positions = [
+            (0, 0, 0),
+            (-1, 0, 0),
+            (0, -1, 0),
+            (-5, -3, 0),
+            (2, -7, 0),
+            (-2, 4, -1),
+            (-3, 4, -1),
+        ];
+        let ids: Vec<TileId> = positions
+            .iter()
+            .map(|pos| grid.insert(*pos, Cell::new(*pos, Kind::Empty)))
+            .collect();
+
+        assert_eq!(grid.len(), positions.len());
+        for (pos, id) in positions.iter().zip(ids) {
+            assert_eq!(grid.id(pos), Some(id));
+            assert_eq!(grid[pos].pos, *pos);
+        }
+        for pos in [(-1, -1, 0), (-6, -3, 0), (-2, 4, 0), (0, 0, -1)] {
+            assert!(!grid.contains_key(&pos));
+        }
+    }
+
+    #[test]
+    fn ids_hold_after_a_remove() {
+        let mut grid = Grid::default();
+        let positions = [(0, 0, 0), (1, 0, 0), (2, 0, 0), (0, 0, 1)];
+        let ids: Vec<TileId> = positions
+            .iter()
+            .map(|pos| grid.insert(*pos, Cell::new(*pos, Kind::Empty)))
+            .collect();
+
+        assert_eq!(grid.remove(&(1, 0, 0)).unwrap().pos, (1, 0, 0));
+        assert!(grid.remove(&(1, 0, 0)).is_none());
+        assert_eq!(grid.len(), 3);
+        assert!(!grid.contains_key(&(1, 0, 0)));
+        for i in [0, 2, 3] {
+            assert_eq!(grid.id(&positions[i]), Some(ids[i]));
+            assert_eq!(grid.tile(ids[i]).pos, positions[i]);
+        }
+
+        // A new tile, even in the same place, gets an id of its own, and
+        // replacing a tile keeps its id.
+        let id = grid.insert((1, 0, 0), Cell::new((1, 0, 0), Kind::Empty));
+        assert!(!ids.contains(&id));
+        assert_eq!(
+            grid.insert((2, 0, 0), Cell::new((2, 0, 0), Kind::Blue)),
+            ids[2]
+        );
+        assert_eq!(grid.tile(ids[2]).kind, Kind::Blue);
+        assert_eq!(grid.len(), 4);
+    }
+}
@@ -0,0 +1,201 @@
+use super::{Direction, Event, Kind, Maze, Position, Side, DIRECTIONS};
+
+/// Outcome of [`Maze::relocalize`].
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Relocalization {
+    /// The scan only fits this tile and heading, or fits it as well as any
+    /// other and it is where the robot was at the last checkpoint. The robot
+    /// is now there.
+    Found(Position, Direction),
+    /// The scan fits all of these equally well, and none of them is the
+    /// checkpoint pose. The robot was left where it was, and shouldn't go on
+    /// until it is put back.
+    Ambiguous(Vec<(Position, Direction)>),
+    /// No known tile has these walls.
+    NotFound,
+}
+
+impl Maze {
+    /// How many of the scanned sides the map agrees with if the robot is on
+    /// `pos` facing `heading`, or `None` if any of them disagrees.
+    fn scan_matches(&self, pos: Position, heading: Direction, scan: [bool; 4]) -> Option<usize> {
+        let cell = self.cells.get(&pos)?;
+        let sides = [heading, heading.right(), heading.left(), heading.back()];
+        let mut matches = 0;
+        for (open, direction) in scan.into_iter().zip(sides) {
+            match (cell.side(direction), open) {
+                (Side::Unknown, _) => (),
+                (Side::Open, true) | (Side::Wall, false) => matches += 1,
+                _ => return None,
+            }
+        }
+        Some(matches)
+    }
+
+    /// Poses among `candidates` that fit the scan best, with the map agreeing
+    /// on at least `least` of the scanned sides.
+    fn best_poses(
+        &self,
+        candidates: impl Iterator<Item = Position>,
+        scan: [bool; 4],
+        least: usize,
+    ) -> Vec<(Position, Direction)> {
+        let mut best = vec![];
+        let mut most = least;
+        for pos in candidates {
+            for heading in DIRECTIONS {
+                let Some(matches) = self.scan_matches(pos, heading, scan) else {
+                    continue;
+                };
+                if matches > most {
+                    most = matches;
+                    best.clear();
+                }
+                if matches == most {
+                    best.push((pos, heading));
+                }
+            }
+        }
+        best
+    }
+
+    /// Works out where the robot is from the sides it sees open, after it
+    /// has been picked up and put down again.
+    ///
+    /// The last checkpoint is tried first, since that is where the robot is
+    /// put back after a lack of progress, then every tile the robot has
+    /// been on. If only one tile and heading fit, or the tile and heading
+    /// the robot had at the last checkpoint fit as well as any other, the
+    /// robot pose is moved there and the planned path is dropped.
+    pub fn relocalize(
+        &mut self,
+        front: bool,
+        right: bool,
+        left: bool,
+        back: bool,
+    ) -> Relocalization {
+        let scan = [front, right, left, back];
+        let mut best = self.best_poses([self.last_checkpoint].into_iter(), scan, 0);
+        if best.is_empty() {
+            let visited: Vec<Position> = self
+                .cells
+                .values()
+                .filter(|cell| !matches!(cell.kind, Kind::Unknown | Kind::Black | Kind::Ramp))
+                .map(|cell| cell.pos)
+                .collect();
+            best = self.best_poses(visited.into_iter(), scan, 1);
+        }
+
+        // A robot put back the way it was is the likeliest of the poses
+        // that fit, and the walls around a checkpoint often look the same
+        // from two headings.
+        let saved = (self.last_checkpoint, self.checkpoint.dir);
+        if best.len() > 1 && best.contains(&saved) {
+            best = vec![saved];
+        }
+
+        match best.as_slice() {
+            [] => Relocalization::NotFound,
+            [(pos, dir)] => {
+                self.pos = *pos;
+                self.dir = *dir;
+                self.path.clear();
+                self.emit(Event::Move {
+                    pos: *pos,
+                    dir: *dir,
+                });
+                Relocalization::Found(*pos, *dir)
+            }
+            _ => Relocalization::Ambiguous(best),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Cell;
+    use super::*;
+
+    const CHECKPOINT: Position = (0, 0, 0);
+    const CORNER: Position = (0, -1, 0);
+
+    // A tile with walls on every side but `open`.
+    fn tile(maze: &mut Maze, pos: Position, kind: Kind, open: &[Direction]) {
+        let mut cell = Cell::new(pos, kind);
+        for direction in DIRECTIONS {
+            let side = if open.contains(&direction) {
+                Side::Open
+            } else {
+                Side::Wall
+            };
+            cell.walls.insert(direction, side);
+        }
+        maze.cells.insert(pos, cell);
+    }
+
+    // A checkpoint on a corridor running up and down, saved facing `dir`,
+    // leading to a corner that turns right.
+    fn maze(dir: Direction) -> Maze {
+        let mut maze = Maze::new();
+        tile(
+            &mut maze,
+            CHECKPOINT,
+            Kind::Checkpoint,
+            &[Direction::Up, Direction::Down],
+        );
+        tile(
+            &mut maze,
+            CORNER,
+            Kind::Empty,
+            &[Direction::Down, Direction::Right],
+        );
+        maze.last_checkpoint = CHECKPOINT;
+        maze.checkpoint.dir = dir;
+        maze.pos = (5, 5, 0);
+        maze.path = vec![(5, 4, 0)];
+        maze
+    }
+
+    #[test]
+    fn the_checkpoint_pose_breaks_ties() {
+        let mut maze = maze(Direction::Down);
+        let found = maze.relocalize(true, false, false, true);
+        assert_eq!(found, Relocalization::Found(CHECKPOINT, Direction::Down));
+        assert_eq!((maze.pos, maze.dir), (CHECKPOINT, Direction::Down));
+        assert!(maze.path.is_empty());
+    }
+
+    #[test]
+    fn other_tiles_are_tried_when_the_checkpoint_doesnt_fit() {
+        let mut maze = maze(Direction::Up);
+        let found = maze.relocalize(true, true, false, false);
+        assert_eq!(found, Relocalization::Found(CORNER, Direction::Right));
+        assert_eq!((maze.pos, maze.dir), (CORNER, Direction::Right));
+    }
+
+    #[test]
+    fn ambiguous_without_the_checkpoint_pose() {
+        // Saved facing a wall, which the scan can't fit.
+        let mut maze = maze(Direction::Left);
+        let found = maze.relocalize(true, false, false, true);
+        assert_eq!(
+            found,
+            Relocalization::Ambiguous(vec![
+                (CHECKPOINT, Direction::Up),
+                (CHECKPOINT, Direction::Down)
+            ])
+        );
+        assert_eq!(maze.pos, (5, 5, 0));
+        assert_eq!(maze.path, [(5, 4, 0)]);
+    }
+
+    #[test]
+    fn not_found_when_nothing_fits() {
+        let mut maze = maze(Direction::Up);
+        assert_eq!(
+            maze.relocalize(true, true, true, true),
+            Relocalization::NotFound
+        );
+        assert_eq!(maze.pos, (5, 5, 0));
+    }
+}
@@ -0,0 +1,227 @@
+use super::{Direction, Event, Maze, Position};
+use anyhow::{bail, Result};
+
+/// A quarter of a tile, as seen on the map with up being -y.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Quarter {
+    UpLeft,
+    UpRight,
+    DownRight,
+    DownLeft,
+}
+
+/// Clockwise, so that turning a quarter turn right moves one step along.
+pub const QUARTERS: [Quarter; 4] = [
+    Quarter::UpLeft,
+    Quarter::UpRight,
+    Quarter::DownRight,
+    Quarter::DownLeft,
+];
+
+// Quarter turns to the right from facing up.
+fn quarter_turns(heading: Direction) -> usize {
+    match heading {
+        Direction::Up => 0,
+        Direction::Right => 1,
+        Direction::Down => 2,
+        Direction::Left => 3,
+    }
+}
+
+/// The quarters of a tile an obstacle takes up.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub struct Obstacle {
+    blocked: [bool; 4],
+}
+
+impl Obstacle {
+    pub fn new(quarters: &[Quarter]) -> Self {
+        let mut obstacle = Obstacle::default();
+        for quarter in quarters {
+            obstacle.blocked[*quarter as usize] = true;
+        }
+        obstacle
+    }
+
+    pub fn is_blocked(&self, quarter: Quarter) -> bool {
+        self.blocked[quarter as usize]
+    }
+
+    pub fn quarters(&self) -> impl Iterator<Item = Quarter> + '_ {
+        QUARTERS
+            .into_iter()
+            .filter(|quarter| self.is_blocked(*quarter))
+    }
+
+    /// Whether a robot driving across the tile towards `direction` can get
+    /// past, which takes a free half of the tile along that direction.
+    pub fn passable(&self, direction: Direction) -> bool {
+        let free = |a: Quarter, b: Quarter| !self.is_blocked(a) && !self.is_blocked(b);
+        match direction {
+            Direction::Up | Direction::Down => {
+                free(Quarter::UpLeft, Quarter::DownLeft)
+                    || free(Quarter::UpRight, Quarter::DownRight)
+            }
+            Direction::Left | Direction::Right => {
+                free(Quarter::UpLeft, Quarter::UpRight)
+                    || free(Quarter::DownLeft, Quarter::DownRight)
+            }
+        }
+    }
+
+    fn turned(&self, quarter_turns: usize) -> Obstacle {
+        let mut turned = Obstacle::default();
+        for (index, blocked) in self.blocked.iter().enumerate() {
+            turned.blocked[(index + quarter_turns) % 4] = *blocked;
+        }
+        turned
+    }
+
+    /// The obstacle as a robot facing `heading` sees it, `UpLeft` being front
+    /// left.
+    pub fn seen_facing(&self, heading: Direction) -> Obstacle {
+        self.turned(4 - quarter_turns(heading))
+    }
+
+    // Back from what a robot facing `heading` sees to the map.
+    fn on_map(&self, heading: Direction) -> Obstacle {
+        self.turned(quarter_turns(heading))
+    }
+
+    // `None` if nothing is blocked, the way a tile stores it.
+    fn or_clear(self) -> Option<Obstacle> {
+        (self != Obstacle::default()).then_some(self)
+    }
+
+    pub(super) fn bits(&self) -> String {
+        self.blocked
+            .iter()
+            .map(|blocked| if *blocked { '1' } else { '0' })
+            .collect()
+    }
+
+    pub(super) fn from_bits(bits: &str) -> Option<Obstacle> {
+        let mut obstacle = Obstacle::default();
+        if bits.len() != 4 {
+            return None;
+        }
+        for (blocked, bit) in obstacle.blocked.iter_mut().zip(bits.chars()) {
+            *blocked = match bit {
+                '0' => false,
+                '1' => true,
+                _ => return None,
+            };
+        }
+        Some(obstacle)
+    }
+}
+
+impl Maze {
+    /// Registers an obstacle on the tile at `side` of the robot (`Up` being
+    /// the one in front of it), with its quarters as the robot sees them
+    /// (`UpLeft` being front left). An obstacle with no quarters blocked
+    /// clears the tile.
+    ///
+    /// Fails if the map has no tile there, which takes scanning that side
+    /// open first.
+    pub fn add_obstacle(&mut self, side: Direction, obstacle: Obstacle) -> Result<()> {
+        let pos = self.neighbor(self.pos, self.absolute_direction(side));
+        let obstacle = obstacle.on_map(self.dir);
+        let Some(cell) = self.cells.get(&pos) else {
+            bail!("No tile at {:?} for the obstacle", pos);
+        };
+        if cell.obstacle == obstacle.or_clear() {
+            return Ok(());
+        }
+        self.emit(Event::Obstacle { pos, obstacle });
+        self.set_obstacle(pos, obstacle);
+        Ok(())
+    }
+
+    pub(super) fn set_obstacle(&mut self, pos: Position, obstacle: Obstacle) {
+        let Some(cell) = self.cells.get_mut(&pos) else {
+            return;
+        };
+        cell.obstacle = obstacle.or_clear();
+        self.path.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADINGS: [Direction; 4] = [
+        Direction::Up,
+        Direction::Right,
+        Direction::Down,
+        Direction::Left,
+    ];
+
+    #[test]
+    fn seen_facing_turns_with_the_robot() {
+        let obstacle = Obstacle::new(&[Quarter::UpLeft]);
+        let seen = [
+            Quarter::UpLeft,
+            Quarter::DownLeft,
+            Quarter::DownRight,
+            Quarter::UpRight,
+        ];
+        for (heading, quarter) in HEADINGS.into_iter().zip(seen) {
+            assert_eq!(
+                obstacle.seen_facing(heading),
+                Obstacle::new(&[quarter]),
+                "{:?}",
+                heading
+            );
+        }
+    }
+
+    #[test]
+    fn on_map_undoes_seen_facing() {
+        let obstacles = [
+            Obstacle::new(&[Quarter::UpRight]),
+            Obstacle::new(&[Quarter::UpLeft, Quarter::DownLeft]),
+            Obstacle::new(&[Quarter::UpLeft, Quarter::UpRight, Quarter::DownRight]),
+        ];
+        for obstacle in obstacles {
+            for heading in HEADINGS {
+                assert_eq!(obstacle.seen_facing(heading).on_map(heading), obstacle);
+            }
+        }
+    }
+
+    #[test]
+    fn passable_takes_a_free_half() {
+        let corner = Obstacle::new(&[Quarter::UpLeft]);
+        let top = Obstacle::new(&[Quarter::UpLeft, Quarter::UpRight]);
+        let across = Obstacle::new(&[Quarter::UpLeft, Quarter::DownRight]);
+        for direction in HEADINGS {
+            let sideways = matches!(direction, Direction::Left | Direction::Right);
+            assert!(corner.passable(direction));
+            assert_eq!(top.passable(direction), sideways);
+            assert!(!across.passable(direction));
+        }
+    }
+
+    #[test]
+    fn obstacles_go_on_the_map_as_seen() {
+        let mut maze = Maze::new();
+        maze.dir = Direction::Right;
+        maze.robot_scan(true, false, false, false);
+
+        // Front left of a robot facing right is the top right of the map.
+        let seen = Obstacle::new(&[Quarter::UpLeft]);
+        maze.add_obstacle(Direction::Up, seen).unwrap();
+        assert_eq!(
+            maze.cells[&(1, 0, 0)].obstacle,
+            Some(Obstacle::new(&[Quarter::UpRight]))
+        );
+
+        maze.add_obstacle(Direction::Up, Obstacle::default())
+            .unwrap();
+        assert_eq!(maze.cells[&(1, 0, 0)].obstacle, None);
+
+        assert!(maze.add_obstacle(Direction::Right, seen).is_err());
+    }
+}
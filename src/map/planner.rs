@@ -0,0 +1,268 @@
+use super::{Cell, Direction, Kind, Maze, Position, DIRECTIONS};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+
+/// Cost of each action the planner can take. Every move pays `step`, turning
+/// in place pays `turn` per quarter turn and entering a blue tile or a ramp
+/// adds `blue` or `ramp` on top. Driving around an obstacle that leaves room
+/// to get past adds `obstacle`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Costs {
+    pub step: u32,
+    pub turn: u32,
+    pub blue: u32,
+    pub ramp: u32,
+    pub obstacle: u32,
+}
+
+impl Default for Costs {
+    fn default() -> Self {
+        Costs {
+            step: 10,
+            turn: 5,
+            blue: 30,
+            ramp: 20,
+            obstacle: 15,
+        }
+    }
+}
+
+pub(super) struct Route {
+    pub path: Vec<Position>,
+    pub cost: u32,
+}
+
+type State = (Position, Direction);
+
+fn quarter_turns(from: Direction, to: Direction) -> u32 {
+    if from == to {
+        0
+    } else if from.back() == to {
+        2
+    } else {
+        1
+    }
+}
+
+fn distance(a: Position, b: Position) -> u32 {
+    a.0.abs_diff(b.0) + a.1.abs_diff(b.1)
+}
+
+// Cost of driving onto `cell` facing `heading` before turning to `direction`.
+fn enter_cost(costs: &Costs, heading: Direction, direction: Direction, cell: &Cell) -> Option<u32> {
+    let mut cost = costs.step + quarter_turns(heading, direction) * costs.turn;
+    match cell.kind {
+        Kind::Black => return None,
+        Kind::Blue => cost += costs.blue,
+        Kind::Ramp => cost += costs.ramp,
+        _ => (),
+    }
+    match cell.obstacle {
+        Some(obstacle) if !obstacle.passable(direction) => return None,
+        Some(_) => cost += costs.obstacle,
+        None => (),
+    }
+    Some(cost)
+}
+
+// The robot has to get off an obstacle's tile towards `direction` as well as
+// onto the next one.
+fn move_cost(
+    costs: &Costs,
+    heading: Direction,
+    from: &Cell,
+    direction: Direction,
+    cell: &Cell,
+) -> Option<u32> {
+    match from.obstacle {
+        Some(obstacle) if !obstacle.passable(direction) => None,
+        _ => enter_cost(costs, heading, direction, cell),
+    }
+}
+
+impl Maze {
+    /// Cost of driving from `from` facing `heading` into `direction`, onto
+    /// `cell`. `None` if `from` can't be left or `cell` can't be entered
+    /// that way.
+    pub(super) fn move_cost(
+        &self,
+        heading: Direction,
+        from: &Cell,
+        direction: Direction,
+        cell: &Cell,
+    ) -> Option<u32> {
+        move_cost(&self.costs, heading, from, direction, cell)
+    }
+
+    /// Cost of having driven onto `cell` facing `heading` before turning to
+    /// `direction`, whether or not it could be done.
+    pub(super) fn enter_cost(&self, heading: Direction, direction: Direction, cell: &Cell) -> u32 {
+        enter_cost(&self.costs, heading, direction, cell).unwrap_or(self.costs.step)
+    }
+
+    /// Whether the planned path can still be driven from the robot pose:
+    /// it starts next to the robot, every step leads to the next through an
+    /// open side and none of them has turned into a tile that can't be
+    /// entered.
+    pub(super) fn path_is_clear(&self) -> bool {
+        self.is_clear(&self.path)
+    }
+
+    pub(super) fn is_clear(&self, path: &[Position]) -> bool {
+        let (mut current, mut heading) = (self.pos, self.dir);
+        for next in path {
+            let Some(cell) = self.cells.get(&current) else {
+                return false;
+            };
+            let Some(direction) = DIRECTIONS
+                .into_iter()
+                .find(|direction| cell.open_neighbor(*direction) == Some(next))
+            else {
+                return false;
+            };
+            let Some(next_cell) = self.cells.get(next) else {
+                return false;
+            };
+            if self
+                .move_cost(heading, cell, direction, next_cell)
+                .is_none()
+            {
+                return false;
+            }
+            (current, heading) = (*next, direction);
+        }
+        true
+    }
+
+    pub(super) fn plan(&self, tar: Kind) -> Option<Route> {
+        self.plan_with(tar, &self.costs)
+    }
+
+    /// A* from the robot pose to the cheapest tile of kind `tar`.
+    ///
+    /// Every move costs at least `costs.step` and changes the x/y distance to
+    /// a tile by at most one (a ramp keeps x/y continuous across floors), so
+    /// `step` times the distance to the nearest target never overestimates.
+    pub(super) fn plan_with(&self, tar: Kind, costs: &Costs) -> Option<Route> {
+        let targets: Vec<Position> = self
+            .cells
+            .iter()
+            .filter(|(_, cell)| cell.kind == tar)
+            .map(|(pos, _)| *pos)
+            .collect();
+        let heuristic = |pos: Position| {
+            targets
+                .iter()
+                .map(|target| distance(pos, *target))
+                .min()
+                .unwrap_or(0)
+                * costs.step
+        };
+
+        let start: State = (self.pos, self.dir);
+        let mut queue = BinaryHeap::new();
+        let mut best: HashMap<State, u32> = HashMap::new();
+        let mut parent: HashMap<State, State> = HashMap::new();
+        queue.push(Reverse((heuristic(self.pos), 0, start)));
+        best.insert(start, 0);
+
+        let mut found = None;
+        while let Some(Reverse((_, cost, state))) = queue.pop() {
+            if best.get(&state).is_some_and(|best| cost > *best) {
+                continue;
+            }
+            let (current, heading) = state;
+            let Some(cell) = self.cells.get(&current) else {
+                continue;
+            };
+            if cell.kind == tar {
+                found = Some((state, cost));
+                break;
+            }
+            for direction in DIRECTIONS {
+                let Some(neighbor) = cell.open_neighbor(direction) else {
+                    continue;
+                };
+                let Some(neighbor_cell) = self.cells.get(neighbor) else {
+                    continue;
+                };
+                let Some(step) = move_cost(costs, heading, cell, direction, neighbor_cell) else {
+                    continue;
+                };
+                let next: State = (*neighbor, direction);
+                let next_cost = cost + step;
+                if best.get(&next).is_none_or(|best| next_cost < *best) {
+                    best.insert(next, next_cost);
+                    parent.insert(next, state);
+                    queue.push(Reverse((next_cost + heuristic(*neighbor), next_cost, next)));
+                }
+            }
+        }
+
+        let (target, cost) = found?;
+        let mut path = vec![];
+        let mut current = target;
+        while current != start {
+            path.push(current.0);
+            current = parent[&current];
+        }
+        path.reverse();
+
+        Some(Route { path, cost })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Obstacle, Quarter};
+    use super::*;
+
+    const AHEAD: Position = (0, -1, 0);
+    const TARGET: Position = (1, -1, 0);
+
+    // The start, the tile in front of it and the target to the right of
+    // that one, with a way round through the tile right of the start when
+    // `round` is set.
+    fn maze(round: bool, obstacle: &[Quarter]) -> Maze {
+        let mut maze = Maze::new();
+        maze.link((0, 0, 0), Direction::Up, AHEAD);
+        maze.link(AHEAD, Direction::Right, TARGET);
+        if round {
+            maze.link((0, 0, 0), Direction::Right, (1, 0, 0));
+            maze.link((1, 0, 0), Direction::Up, TARGET);
+        }
+        for pos in [AHEAD, (1, 0, 0)] {
+            if let Some(cell) = maze.cells.get_mut(&pos) {
+                cell.kind = Kind::Empty;
+            }
+        }
+        maze.cells.get_mut(&TARGET).unwrap().kind = Kind::Checkpoint;
+        maze.cells.get_mut(&AHEAD).unwrap().obstacle = Some(Obstacle::new(obstacle));
+        maze
+    }
+
+    #[test]
+    fn plans_round_an_obstacle_it_cant_get_past() {
+        let maze = maze(true, &[Quarter::UpLeft, Quarter::UpRight]);
+        let route = maze.plan(Kind::Checkpoint).unwrap();
+        assert_eq!(route.path, [(1, 0, 0), TARGET]);
+    }
+
+    #[test]
+    fn plans_past_an_obstacle_that_leaves_room() {
+        let maze = maze(false, &[Quarter::UpLeft]);
+        let route = maze.plan(Kind::Checkpoint).unwrap();
+        assert_eq!(route.path, [AHEAD, TARGET]);
+        let costs = Costs::default();
+        assert_eq!(route.cost, 2 * costs.step + costs.turn + costs.obstacle);
+        assert!(maze.is_clear(&route.path));
+    }
+
+    #[test]
+    fn an_obstacle_has_to_be_left_the_way_the_path_goes() {
+        // Room to drive in from below, but not to turn off to the right.
+        let maze = maze(false, &[Quarter::UpLeft, Quarter::DownLeft]);
+        assert!(maze.plan(Kind::Checkpoint).is_none());
+        assert!(!maze.is_clear(&[AHEAD, TARGET]));
+    }
+}
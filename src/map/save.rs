@@ -0,0 +1,491 @@
+use super::consistency::Votes;
+use super::grid::Grid;
+use super::{
+    Cell, Direction, Kind, Maze, Obstacle, Position, Side, Snapshot, Victim, VictimKey, VictimKind,
+    DIRECTIONS, RESCUE_KITS,
+};
+use anyhow::{anyhow, bail, Result};
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const MAGIC: &str = "rusty_capybara-maze";
+// Every version before this one only lacks lines added since, except for
+// version 1 counting victims with a `victims` line, so older saves still load:
+// what they don't have is left empty, and the rescue kits left are worked
+// out from the victims served.
+const VERSION: u32 = 7;
+
+pub(super) fn direction_name(direction: Direction) -> &'static str {
+    match direction {
+        Direction::Up => "U",
+        Direction::Down => "D",
+        Direction::Left => "L",
+        Direction::Right => "R",
+    }
+}
+
+pub(super) fn parse_direction(token: &str) -> Result<Direction> {
+    match token {
+        "U" => Ok(Direction::Up),
+        "D" => Ok(Direction::Down),
+        "L" => Ok(Direction::Left),
+        "R" => Ok(Direction::Right),
+        _ => Err(anyhow!("Invalid direction: {}", token)),
+    }
+}
+
+pub(super) fn kind_name(kind: Kind) -> &'static str {
+    match kind {
+        Kind::Start => "start",
+        Kind::Unknown => "unknown",
+        Kind::Empty => "empty",
+        Kind::Checkpoint => "checkpoint",
+        Kind::Blue => "blue",
+        Kind::Ramp => "ramp",
+        Kind::Black => "black",
+    }
+}
+
+pub(super) fn parse_kind(token: &str) -> Result<Kind> {
+    match token {
+        "start" => Ok(Kind::Start),
+        "unknown" => Ok(Kind::Unknown),
+        "empty" => Ok(Kind::Empty),
+        "checkpoint" => Ok(Kind::Checkpoint),
+        "blue" => Ok(Kind::Blue),
+        "ramp" => Ok(Kind::Ramp),
+        "black" => Ok(Kind::Black),
+        _ => Err(anyhow!("Invalid tile kind: {}", token)),
+    }
+}
+
+pub(super) fn side_name(side: Side) -> &'static str {
+    match side {
+        Side::Wall => "wall",
+        Side::Open => "open",
+        Side::Unknown => "unknown",
+    }
+}
+
+pub(super) fn parse_side(token: &str) -> Result<Side> {
+    match token {
+        "wall" => Ok(Side::Wall),
+        "open" => Ok(Side::Open),
+        "unknown" => Ok(Side::Unknown),
+        _ => Err(anyhow!("Invalid side: {}", token)),
+    }
+}
+
+pub(super) fn parse_obstacle(token: &str) -> Result<Obstacle> {
+    Obstacle::from_bits(token).ok_or_else(|| anyhow!("Invalid obstacle: {}", token))
+}
+
+pub(super) fn parse_position(tokens: &[&str]) -> Result<Position> {
+    if tokens.len() != 3 {
+        bail!("Expected a position, got: {}", tokens.join(" "));
+    }
+    Ok((tokens[0].parse()?, tokens[1].parse()?, tokens[2].parse()?))
+}
+
+fn write_snapshot(out: &mut String, snapshot: &Snapshot) {
+    writeln!(out, "dir {}", direction_name(snapshot.dir)).unwrap();
+    let (x, y, level) = snapshot.pos;
+    writeln!(out, "pos {} {} {}", x, y, level).unwrap();
+    writeln!(out, "rescue_kits {}", snapshot.rescue_kits).unwrap();
+    write!(out, "path").unwrap();
+    for (x, y, level) in &snapshot.path {
+        write!(out, " {} {} {}", x, y, level).unwrap();
+    }
+    writeln!(out).unwrap();
+
+    let mut positions: Vec<&Position> = snapshot.cells.keys().collect();
+    positions.sort();
+    for pos in positions {
+        let cell = &snapshot.cells[pos];
+        let (x, y, level) = *pos;
+        writeln!(out, "cell {} {} {} {}", x, y, level, kind_name(cell.kind)).unwrap();
+        if let Some(obstacle) = cell.obstacle {
+            writeln!(out, "obstacle {} {} {} {}", x, y, level, obstacle.bits()).unwrap();
+        }
+        for direction in DIRECTIONS {
+            if let Some(side) = cell.walls.get(&direction) {
+                writeln!(
+                    out,
+                    "side {} {} {} {} {}",
+                    x,
+                    y,
+                    level,
+                    direction_name(direction),
+                    side_name(*side)
+                )
+                .unwrap();
+            }
+            if let Some((nx, ny, nlevel)) = cell.neighbors.get(&direction) {
+                writeln!(
+                    out,
+                    "neighbor {} {} {} {} {} {} {}",
+                    x,
+                    y,
+                    level,
+                    direction_name(direction),
+                    nx,
+                    ny,
+                    nlevel
+                )
+                .unwrap();
+            }
+        }
+    }
+
+    let mut victims: Vec<_> = snapshot.victims.iter().collect();
+    victims.sort_by_key(|((pos, side), _)| (*pos, *side));
+    for (((x, y, level), side), victim) in victims {
+        writeln!(
+            out,
+            "victim {} {} {} {} {} {} {}",
+            x,
+            y,
+            level,
+            direction_name(*side),
+            victim.kind.label(),
+            victim.kits,
+            victim.served
+        )
+        .unwrap();
+    }
+
+    let mut beliefs: Vec<_> = snapshot.beliefs.iter().collect();
+    beliefs.sort_by_key(|(edge, _)| **edge);
+    for (((x, y, level), side), belief) in beliefs {
+        writeln!(
+            out,
+            "belief {} {} {} {} {}",
+            x,
+            y,
+            level,
+            direction_name(*side),
+            belief
+        )
+        .unwrap();
+    }
+
+    let mut scans: Vec<_> = snapshot.scans.iter().collect();
+    scans.sort_by_key(|(edge, _)| **edge);
+    for (((x, y, level), side), votes) in scans {
+        writeln!(
+            out,
+            "scan {} {} {} {} {} {} {}",
+            x,
+            y,
+            level,
+            direction_name(*side),
+            votes.walls,
+            votes.opens,
+            side_name(votes.last)
+        )
+        .unwrap();
+    }
+}
+
+// Kits a robot starting with a full load has left after serving `victims`.
+fn kits_left(victims: &HashMap<VictimKey, Victim>) -> u8 {
+    let served: u32 = victims
+        .values()
+        .filter(|victim| victim.served)
+        .map(|victim| victim.kits as u32)
+        .sum();
+    RESCUE_KITS.saturating_sub(served.min(RESCUE_KITS as u32) as u8)
+}
+
+fn parse_snapshot<'a>(
+    lines: &mut impl Iterator<Item = (usize, &'a str)>,
+    version: u32,
+) -> Result<Snapshot> {
+    let mut rescue_kits = None;
+    let mut snapshot = Snapshot {
+        dir: Direction::Up,
+        pos: (0, 0, 0),
+        cells: Grid::default(),
+        path: vec![],
+        victims: HashMap::new(),
+        beliefs: HashMap::new(),
+        scans: HashMap::new(),
+        rescue_kits: 0,
+    };
+    for (number, line) in lines {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        let result = match tokens.as_slice() {
+            ["end"] => {
+                snapshot.rescue_kits = rescue_kits.unwrap_or_else(|| kits_left(&snapshot.victims));
+                return Ok(snapshot);
+            }
+            ["dir", dir] => parse_direction(dir).map(|dir| snapshot.dir = dir),
+            ["pos", rest @ ..] => parse_position(rest).map(|pos| snapshot.pos = pos),
+            ["rescue_kits", kits] => kits
+                .parse()
+                .map(|kits| rescue_kits = Some(kits))
+                .map_err(Into::into),
+            // Found victims were only counted.
+            ["victims", _] if version == 1 => Ok(()),
+            ["victim", x, y, level, side, kind, kits, served] => parse_position(&[x, y, level])
+                .and_then(|pos| {
+                    let kind = VictimKind::from_label(kind)
+                        .ok_or_else(|| anyhow!("Invalid victim: {}", kind))?;
+                    let victim = Victim {
+                        kind,
+                        kits: kits.parse()?,
+                        served: served.parse()?,
+                    };
+                    snapshot
+                        .victims
+                        .insert((pos, parse_direction(side)?), victim);
+                    Ok(())
+                }),
+            ["belief", x, y, level, side, belief] => {
+                parse_position(&[x, y, level]).and_then(|pos| {
+                    snapshot
+                        .beliefs
+                        .insert((pos, parse_direction(side)?), belief.parse()?);
+                    Ok(())
+                })
+            }
+            ["scan", x, y, level, side, walls, opens, last] => parse_position(&[x, y, level])
+                .and_then(|pos| {
+                    let votes = Votes {
+                        walls: walls.parse()?,
+                        opens: opens.parse()?,
+                        last: parse_side(last)?,
+                        contested: false,
+                    };
+                    snapshot.scans.insert((pos, parse_direction(side)?), votes);
+                    Ok(())
+                }),
+            ["path", rest @ ..] => rest
+                .chunks(3)
+                .map(parse_position)
+                .collect::<Result<Vec<_>>>()
+                .map(|path| snapshot.path = path),
+            ["cell", x, y, level, kind] => parse_position(&[x, y, level]).and_then(|pos| {
+                snapshot
+                    .cells
+                    .insert(pos, Cell::new(pos, parse_kind(kind)?));
+                Ok(())
+            }),
+            ["obstacle", x, y, level, bits] => parse_position(&[x, y, level]).and_then(|pos| {
+                let cell = snapshot
+                    .cells
+                    .get_mut(&pos)
+                    .ok_or_else(|| anyhow!("Obstacle on unknown tile"))?;
+                cell.obstacle = Some(parse_obstacle(bits)?);
+                Ok(())
+            }),
+            ["side", x, y, level, direction, side] => {
+                parse_position(&[x, y, level]).and_then(|pos| {
+                    let cell = snapshot
+                        .cells
+                        .get_mut(&pos)
+                        .ok_or_else(|| anyhow!("Side of unknown tile"))?;
+                    cell.walls
+                        .insert(parse_direction(direction)?, parse_side(side)?);
+                    Ok(())
+                })
+            }
+            ["neighbor", x, y, level, direction, rest @ ..] => parse_position(&[x, y, level])
+                .and_then(|pos| {
+                    let neighbor = parse_position(rest)?;
+                    let cell = snapshot
+                        .cells
+                        .get_mut(&pos)
+                        .ok_or_else(|| anyhow!("Neighbor of unknown tile"))?;
+                    cell.neighbors.insert(parse_direction(direction)?, neighbor);
+                    Ok(())
+                }),
+            _ => Err(anyhow!("Unexpected line")),
+        };
+        result.map_err(|err| anyhow!("Line {}: {}", number + 1, err))?;
+    }
+    bail!("Unexpected end of file")
+}
+
+impl Maze {
+    /// Writes the whole map state, including the checkpoint it would roll back
+    /// to, so that [`Maze::load`] gives back the same maze.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let mut out = String::new();
+        writeln!(out, "{} {}", MAGIC, VERSION).unwrap();
+        let (x, y, level) = self.last_checkpoint;
+        writeln!(out, "last_checkpoint {} {} {}", x, y, level).unwrap();
+        writeln!(out, "returning {}", self.returning).unwrap();
+        writeln!(out, "current").unwrap();
+        write_snapshot(&mut out, &self.snapshot());
+        writeln!(out, "end").unwrap();
+        writeln!(out, "checkpoint").unwrap();
+        write_snapshot(&mut out, &self.checkpoint);
+        writeln!(out, "end").unwrap();
+
+        // Write next to the old save and swap, so a power cut mid-write
+        // leaves the previous save intact.
+        let path = path.as_ref();
+        let tmp = PathBuf::from(format!("{}.tmp", path.display()));
+        fs::write(&tmp, out)?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Maze> {
+        let text = fs::read_to_string(path)?;
+        let mut lines = text
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty());
+
+        let version: u32 = match lines
+            .next()
+            .map(|(_, line)| line.split_whitespace().collect::<Vec<_>>())
+        {
+            Some(header) if header.len() == 2 && header[0] == MAGIC => header[1].parse()?,
+            _ => bail!("Not a maze file"),
+        };
+        if !(1..=VERSION).contains(&version) {
+            bail!("Unsupported maze file version: {}", version);
+        }
+
+        let mut maze = Maze::new();
+        let mut current = None;
+        let mut checkpoint = None;
+        let mut rescue_kits = None;
+        while let Some((number, line)) = lines.next() {
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+            match tokens.as_slice() {
+                ["last_checkpoint", rest @ ..] => maze.last_checkpoint = parse_position(rest)?,
+                ["returning", returning] => maze.returning = returning.parse()?,
+                ["current"] => current = Some(parse_snapshot(&mut lines, version)?),
+                ["checkpoint"] => checkpoint = Some(parse_snapshot(&mut lines, version)?),
+                // Version 6 kept the kits left outside of the snapshots.
+                ["rescue_kits", kits] if version == 6 => rescue_kits = Some(kits.parse()?),
+                _ => bail!("Line {}: Unexpected line", number + 1),
+            }
+        }
+
+        maze.restore(current.ok_or_else(|| anyhow!("Missing current state"))?);
+        if let Some(kits) = rescue_kits {
+            maze.rescue_kits = kits;
+        }
+        maze.checkpoint = checkpoint.ok_or_else(|| anyhow!("Missing checkpoint state"))?;
+        Ok(maze)
+    }
+
+    /// Saves the maze to `path` after every move.
+    pub fn set_autosave(&mut self, path: impl Into<PathBuf>) {
+        self.autosave = Some(path.into());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::obstacle::Quarter;
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("{}-{}", std::process::id(), name))
+    }
+
+    fn assert_same(loaded: &Snapshot, saved: &Snapshot) {
+        assert_eq!(loaded.dir, saved.dir);
+        assert_eq!(loaded.pos, saved.pos);
+        assert_eq!(loaded.path, saved.path);
+        assert_eq!(loaded.victims, saved.victims);
+        assert_eq!(loaded.beliefs, saved.beliefs);
+        assert_eq!(loaded.rescue_kits, saved.rescue_kits);
+
+        assert_eq!(loaded.cells.len(), saved.cells.len());
+        for (pos, cell) in saved.cells.iter() {
+            let other = &loaded.cells[pos];
+            assert_eq!(other.kind, cell.kind);
+            assert_eq!(other.walls, cell.walls);
+            assert_eq!(other.neighbors, cell.neighbors);
+            assert_eq!(other.obstacle, cell.obstacle);
+        }
+
+        // Whether an edge is contested is worked out again from new scans.
+        assert_eq!(loaded.scans.len(), saved.scans.len());
+        for (edge, votes) in &saved.scans {
+            let other = &loaded.scans[edge];
+            assert_eq!(
+                (other.walls, other.opens, other.last),
+                (votes.walls, votes.opens, votes.last)
+            );
+        }
+    }
+
+    #[test]
+    fn saved_mazes_load_the_same() {
+        let mut maze = Maze::new();
+        maze.robot_scan(true, false, true, false);
+        maze.add_checkpoint();
+        maze.add_victim(VictimKind::H, Direction::Right);
+        maze.serve_victim(Direction::Right);
+        maze.add_obstacle(Direction::Up, Obstacle::new(&[Quarter::UpLeft]))
+            .unwrap();
+        maze.move_one();
+        maze.robot_scan(false, true, true, true);
+
+        // Saving must not write over a save whose name already ends in .tmp.
+        let path = temp_path("maze.tmp");
+        maze.save(&path).unwrap();
+        let text = fs::read_to_string(&path).unwrap();
+        let loaded = Maze::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.last_checkpoint, maze.last_checkpoint);
+        assert_eq!(loaded.returning, maze.returning);
+        assert_same(&loaded.snapshot(), &maze.snapshot());
+        assert_same(&loaded.checkpoint, &maze.checkpoint);
+        assert_eq!(loaded.checkpoint.rescue_kits, RESCUE_KITS);
+        assert_eq!(loaded.rescue_kits, RESCUE_KITS - 3);
+
+        loaded.save(&path).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), text);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn version_one_saves_still_load() {
+        let path = temp_path("version-one.maze");
+        fs::write(
+            &path,
+            format!(
+                "{} 1\n\
+                 last_checkpoint 0 0 0\n\
+                 returning false\n\
+                 current\n\
+                 dir R\n\
+                 pos 1 0 0\n\
+                 path\n\
+                 cell 0 0 0 start\n\
+                 cell 1 0 0 empty\n\
+                 victims 1\n\
+                 end\n\
+                 checkpoint\n\
+                 dir U\n\
+                 pos 0 0 0\n\
+                 path\n\
+                 cell 0 0 0 start\n\
+                 victims 0\n\
+                 end\n",
+                MAGIC
+            ),
+        )
+        .unwrap();
+        let maze = Maze::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(maze.pos, (1, 0, 0));
+        assert_eq!(maze.dir, Direction::Right);
+        assert_eq!(maze.cells.len(), 2);
+        assert_eq!(maze.rescue_kits, RESCUE_KITS);
+        assert_eq!(maze.checkpoint.cells.len(), 1);
+    }
+}
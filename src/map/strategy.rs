@@ -0,0 +1,114 @@
+use super::{Costs, Direction, Kind, Maze, Position};
+
+/// Decides where the robot goes next while there is still something to
+/// explore. Going back to the start is left to the [`Maze`].
+pub trait ExplorationStrategy {
+    fn name(&self) -> &'static str;
+
+    /// Tiles to drive through, starting with the one next to the robot, or
+    /// `None` if there is nothing left to explore.
+    fn next_path(&self, maze: &Maze) -> Option<Vec<Position>>;
+}
+
+fn first_unknown(maze: &Maze, preferences: [Direction; 4]) -> Option<Vec<Position>> {
+    let cell = maze.cells.get(&maze.pos)?;
+    preferences.into_iter().find_map(|direction| {
+        let neighbor = cell.open_neighbor(direction)?;
+        match maze.cells.get(neighbor) {
+            Some(neighbor_cell)
+                if neighbor_cell.kind == Kind::Unknown
+                    && maze
+                        .move_cost(maze.dir, cell, direction, neighbor_cell)
+                        .is_some() =>
+            {
+                Some(vec![*neighbor])
+            }
+            _ => None,
+        }
+    })
+}
+
+fn nearest_frontier(maze: &Maze, costs: &Costs) -> Option<Vec<Position>> {
+    maze.plan_with(Kind::Unknown, costs)
+        .map(|route| route.path)
+        .filter(|path| !path.is_empty())
+}
+
+/// Follows the right wall: right, straight, left and back, then the cheapest
+/// unexplored tile once there is none next to the robot.
+pub struct RightHand;
+
+impl ExplorationStrategy for RightHand {
+    fn name(&self) -> &'static str {
+        "right-hand"
+    }
+
+    fn next_path(&self, maze: &Maze) -> Option<Vec<Position>> {
+        let dir = maze.dir;
+        first_unknown(maze, [dir.right(), dir, dir.left(), dir.back()])
+            .or_else(|| nearest_frontier(maze, &maze.costs))
+    }
+}
+
+/// Same as [`RightHand`], following the left wall.
+pub struct LeftHand;
+
+impl ExplorationStrategy for LeftHand {
+    fn name(&self) -> &'static str {
+        "left-hand"
+    }
+
+    fn next_path(&self, maze: &Maze) -> Option<Vec<Position>> {
+        let dir = maze.dir;
+        first_unknown(maze, [dir.left(), dir, dir.right(), dir.back()])
+            .or_else(|| nearest_frontier(maze, &maze.costs))
+    }
+}
+
+/// Always goes to the unexplored tile that is cheapest to reach.
+pub struct NearestFrontier;
+
+impl ExplorationStrategy for NearestFrontier {
+    fn name(&self) -> &'static str {
+        "nearest-frontier"
+    }
+
+    fn next_path(&self, maze: &Maze) -> Option<Vec<Position>> {
+        nearest_frontier(maze, &maze.costs)
+    }
+}
+
+/// Goes to the unexplored tile that takes the fewest turns to reach, and the
+/// cheapest one among those.
+pub struct FewestTurns;
+
+impl ExplorationStrategy for FewestTurns {
+    fn name(&self) -> &'static str {
+        "fewest-turns"
+    }
+
+    fn next_path(&self, maze: &Maze) -> Option<Vec<Position>> {
+        let costs = Costs {
+            turn: maze.costs.turn + 10_000,
+            ..maze.costs
+        };
+        nearest_frontier(maze, &costs)
+    }
+}
+
+pub const STRATEGIES: [&str; 4] = [
+    "right-hand",
+    "left-hand",
+    "nearest-frontier",
+    "fewest-turns",
+];
+
+pub fn strategy(name: &str) -> Option<Box<dyn ExplorationStrategy>> {
+    match name {
+        "right-hand" => Some(Box::new(RightHand)),
+        "left-hand" => Some(Box::new(LeftHand)),
+        "nearest-frontier" => Some(Box::new(NearestFrontier)),
+        "fewest-turns" => Some(Box::new(FewestTurns)),
+        _ => None,
+    }
+}
@@ -0,0 +1,207 @@
+use super::{Direction, Event, Maze, Position};
+use crate::scoring::RunEvent;
+use crate::vision::Detection;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum VictimKind {
+    H,
+    S,
+    U,
+    Red,
+    Yellow,
+    Green,
+}
+
+impl VictimKind {
+    /// Maps a class label of the vision model to a victim.
+    pub fn from_label(label: &str) -> Option<VictimKind> {
+        match label {
+            "H" => Some(VictimKind::H),
+            "S" => Some(VictimKind::S),
+            "U" => Some(VictimKind::U),
+            "RED" => Some(VictimKind::Red),
+            "YELLOW" => Some(VictimKind::Yellow),
+            "GREEN" => Some(VictimKind::Green),
+            _ => None,
+        }
+    }
+
+    pub fn from_detection(detection: &Detection) -> Option<VictimKind> {
+        VictimKind::from_label(&detection.class_label)
+    }
+
+    pub fn label(&self) -> &'static str {
+        match *self {
+            VictimKind::H => "H",
+            VictimKind::S => "S",
+            VictimKind::U => "U",
+            VictimKind::Red => "RED",
+            VictimKind::Yellow => "YELLOW",
+            VictimKind::Green => "GREEN",
+        }
+    }
+
+    /// Rescue kits the rules ask to drop next to this kind of victim.
+    pub fn rescue_kits(&self) -> u8 {
+        match *self {
+            VictimKind::H => 3,
+            VictimKind::S => 2,
+            VictimKind::U => 0,
+            VictimKind::Red => 1,
+            VictimKind::Yellow => 1,
+            VictimKind::Green => 0,
+        }
+    }
+
+    pub(super) fn symbol(&self) -> char {
+        match *self {
+            VictimKind::H => 'H',
+            VictimKind::S => 'S',
+            VictimKind::U => 'U',
+            VictimKind::Red => 'r',
+            VictimKind::Yellow => 'y',
+            VictimKind::Green => 'g',
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Victim {
+    pub kind: VictimKind,
+    pub kits: u8,
+    pub served: bool,
+}
+
+/// A victim is stuck on one face of a wall: the tile it faces and the side
+/// of that tile the wall is on.
+pub(super) type VictimKey = (Position, Direction);
+
+impl Maze {
+    pub(super) fn absolute_direction(&self, side: Direction) -> Direction {
+        match side {
+            Direction::Up => self.dir,
+            Direction::Down => self.dir.back(),
+            Direction::Left => self.dir.left(),
+            Direction::Right => self.dir.right(),
+        }
+    }
+
+    /// Registers a victim seen on the wall at `side` of the robot (`Up` being
+    /// in front of it). Returns the rescue kits to drop, or `None` if the
+    /// victim was already known. Victims are told apart by the face of the
+    /// wall they are on only, so two of the same kind on neighbouring tiles
+    /// are two victims.
+    pub fn add_victim(&mut self, kind: VictimKind, side: Direction) -> Option<u8> {
+        let key = (self.pos, self.absolute_direction(side));
+        if self.victims.contains_key(&key) {
+            return None;
+        }
+        self.victims.insert(
+            key,
+            Victim {
+                kind,
+                kits: kind.rescue_kits(),
+                served: false,
+            },
+        );
+        self.run.push(RunEvent::VictimIdentified {
+            pos: key.0,
+            side: key.1,
+            kind,
+        });
+        self.emit(Event::Victim {
+            pos: key.0,
+            side: key.1,
+            kind,
+        });
+        Some(kind.rescue_kits())
+    }
+
+    /// Marks the victim at `side` of the robot as served and takes its kits
+    /// out of the ones left on board. Returns how many kits to drop.
+    pub fn serve_victim(&mut self, side: Direction) -> u8 {
+        let key = (self.pos, self.absolute_direction(side));
+        let Some(victim) = self.victims.get_mut(&key) else {
+            return 0;
+        };
+        if victim.served {
+            return 0;
+        }
+        victim.served = true;
+        let kits = victim.kits.min(self.rescue_kits);
+        self.rescue_kits -= kits;
+        self.run.push(RunEvent::KitsDropped {
+            pos: key.0,
+            side: key.1,
+            kits,
+        });
+        kits
+    }
+
+    pub fn set_rescue_kits(&mut self, kits: u8) {
+        self.rescue_kits = kits;
+    }
+
+    pub fn rescue_kits(&self) -> u8 {
+        self.rescue_kits
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn victims_are_registered_once_per_face() {
+        let mut maze = Maze::new();
+        assert_eq!(maze.add_victim(VictimKind::H, Direction::Left), Some(3));
+        assert_eq!(maze.add_victim(VictimKind::H, Direction::Left), None);
+        // Another face of the same tile.
+        assert_eq!(maze.add_victim(VictimKind::H, Direction::Right), Some(3));
+
+        // The same kind on the same wall one tile along is another victim.
+        maze.pos = (0, 1, 0);
+        assert_eq!(maze.add_victim(VictimKind::H, Direction::Left), Some(3));
+        assert_eq!(maze.victims().count(), 3);
+    }
+
+    #[test]
+    fn sides_are_relative_to_the_robot() {
+        let mut maze = Maze::new();
+        maze.dir = Direction::Right;
+        assert_eq!(maze.add_victim(VictimKind::S, Direction::Left), Some(2));
+        assert!(maze.victims.contains_key(&((0, 0, 0), Direction::Up)));
+        maze.dir = Direction::Down;
+        assert_eq!(maze.add_victim(VictimKind::S, Direction::Down), None);
+    }
+
+    #[test]
+    fn serving_takes_kits_once() {
+        let mut maze = Maze::new();
+        maze.set_rescue_kits(4);
+        maze.add_victim(VictimKind::H, Direction::Up);
+        maze.add_victim(VictimKind::S, Direction::Down);
+        maze.add_victim(VictimKind::U, Direction::Left);
+
+        assert_eq!(maze.serve_victim(Direction::Up), 3);
+        assert_eq!(maze.serve_victim(Direction::Up), 0);
+        assert_eq!(maze.rescue_kits(), 1);
+        // Only one kit left for the two the S asks for.
+        assert_eq!(maze.serve_victim(Direction::Down), 1);
+        assert_eq!(maze.rescue_kits(), 0);
+        assert_eq!(maze.serve_victim(Direction::Left), 0);
+        assert_eq!(maze.serve_victim(Direction::Right), 0);
+        assert!(maze.victims.values().filter(|victim| victim.served).count() == 3);
+    }
+
+    #[test]
+    fn lack_of_progress_gives_the_kits_back() {
+        let mut maze = Maze::new();
+        maze.add_victim(VictimKind::H, Direction::Up);
+        maze.serve_victim(Direction::Up);
+        assert_eq!(maze.rescue_kits(), 9);
+        maze.lack_of_progress();
+        assert_eq!(maze.rescue_kits(), 12);
+        assert_eq!(maze.victims().count(), 0);
+    }
+}
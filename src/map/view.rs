@@ -0,0 +1,96 @@
+use super::{Cell, Direction, Kind, Maze, Obstacle, Position, Side, Victim};
+
+/// What the map knows about one tile, copied out of the [`Maze`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Tile {
+    pub pos: Position,
+    pub kind: Kind,
+    pub up: Side,
+    pub down: Side,
+    pub left: Side,
+    pub right: Side,
+    pub obstacle: Option<Obstacle>,
+}
+
+impl Tile {
+    fn from_cell(cell: &Cell) -> Self {
+        Tile {
+            pos: cell.pos,
+            kind: cell.kind,
+            up: cell.side(Direction::Up),
+            down: cell.side(Direction::Down),
+            left: cell.side(Direction::Left),
+            right: cell.side(Direction::Right),
+            obstacle: cell.obstacle,
+        }
+    }
+
+    pub fn side(&self, direction: Direction) -> Side {
+        match direction {
+            Direction::Up => self.up,
+            Direction::Down => self.down,
+            Direction::Left => self.left,
+            Direction::Right => self.right,
+        }
+    }
+}
+
+impl Maze {
+    /// Every tile the map knows about, on every floor, in the order they
+    /// were found.
+    pub fn tiles(&self) -> impl Iterator<Item = Tile> + '_ {
+        self.cells.values().map(Tile::from_cell)
+    }
+
+    pub fn tile(&self, pos: Position) -> Option<Tile> {
+        self.cells.get(&pos).map(Tile::from_cell)
+    }
+
+    pub fn kind(&self, pos: Position) -> Option<Kind> {
+        self.cells.get(&pos).map(|cell| cell.kind)
+    }
+
+    pub fn side(&self, pos: Position, direction: Direction) -> Option<Side> {
+        self.cells.get(&pos).map(|cell| cell.side(direction))
+    }
+
+    /// Tile reached by driving from `pos` towards `direction`, if there is no
+    /// wall in between. Follows ramps to the other floor.
+    pub fn neighbor_of(&self, pos: Position, direction: Direction) -> Option<Position> {
+        self.cells
+            .get(&pos)
+            .and_then(|cell| cell.open_neighbor(direction))
+            .copied()
+    }
+
+    /// Where the robot is and which way it is facing.
+    pub fn pose(&self) -> (Position, Direction) {
+        (self.pos, self.dir)
+    }
+
+    pub fn last_checkpoint(&self) -> Position {
+        self.last_checkpoint
+    }
+
+    /// Tiles the robot is going to drive through, next one first.
+    pub fn planned_path(&self) -> &[Position] {
+        &self.path
+    }
+
+    /// Tiles seen through an opening that the robot has not been on yet.
+    pub fn frontier(&self) -> Vec<Position> {
+        self.cells
+            .values()
+            .filter(|cell| cell.kind == Kind::Unknown)
+            .map(|cell| cell.pos)
+            .collect()
+    }
+
+    /// Victims found so far, with the tile they face and the side of that
+    /// tile their wall is on.
+    pub fn victims(&self) -> impl Iterator<Item = (Position, Direction, Victim)> + '_ {
+        self.victims
+            .iter()
+            .map(|((pos, side), victim)| (*pos, *side, *victim))
+    }
+}
@@ -0,0 +1,271 @@
+/*!
+Scoring of a Rescue Maze run.
+
+The [`Maze`](crate::map::Maze) records a [`RunLog`] while it explores, so the
+same log comes out of a real run and out of the simulator, and [`score`]
+turns it into points.
+*/
+#![allow(dead_code)]
+use crate::map::{Direction, Position, VictimKind};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RunEvent {
+    Visited(Position),
+    VictimIdentified {
+        pos: Position,
+        side: Direction,
+        kind: VictimKind,
+    },
+    KitsDropped {
+        pos: Position,
+        side: Direction,
+        kits: u8,
+    },
+    Checkpoint(Position),
+    Ramp(Position),
+    LackOfProgress,
+    Exit,
+}
+
+#[derive(Clone, Default, Debug)]
+pub struct RunLog {
+    pub events: Vec<RunEvent>,
+}
+
+impl RunLog {
+    pub fn push(&mut self, event: RunEvent) {
+        self.events.push(event);
+    }
+}
+
+/// Points awarded for each scoring element. The defaults follow the RoboCup
+/// Junior Rescue Maze rules, counting every victim as one on a linear wall.
+#[derive(Clone, Copy, Debug)]
+pub struct Rules {
+    pub victim: u32,
+    pub kit: u32,
+    pub checkpoint: u32,
+    pub ramp: u32,
+    pub reliability: u32,
+    pub lack_of_progress: u32,
+    pub exit_per_victim: u32,
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Rules {
+            victim: 5,
+            kit: 10,
+            checkpoint: 10,
+            ramp: 20,
+            reliability: 10,
+            lack_of_progress: 10,
+            exit_per_victim: 10,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct Score {
+    pub victims: u32,
+    pub kits: u32,
+    pub checkpoints: u32,
+    pub ramps: u32,
+    pub reliability: u32,
+    pub exit: u32,
+}
+
+impl Score {
+    pub fn total(&self) -> u32 {
+        self.victims + self.kits + self.checkpoints + self.ramps + self.reliability + self.exit
+    }
+}
+
+impl fmt::Display for Score {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Score: {}  (victims {}  kits {}  checkpoints {}  ramps {}  reliability {}  exit {})",
+            self.total(),
+            self.victims,
+            self.kits,
+            self.checkpoints,
+            self.ramps,
+            self.reliability,
+            self.exit
+        )
+    }
+}
+
+/// Scores a run. Every victim, checkpoint and ramp only scores the first
+/// time, even if the robot finds it again after a lack of progress. Tiles
+/// driven over don't score by themselves.
+pub fn score(log: &RunLog, rules: &Rules) -> Score {
+    let mut victims: HashMap<(Position, Direction), (u8, u8)> = HashMap::new();
+    let mut checkpoints = HashSet::new();
+    let mut ramps = HashSet::new();
+    let mut lack_of_progress = 0;
+    let mut exited = false;
+
+    for event in &log.events {
+        match *event {
+            RunEvent::Visited(_) => (),
+            RunEvent::VictimIdentified { pos, side, kind } => {
+                victims
+                    .entry((pos, side))
+                    .or_insert((kind.rescue_kits(), 0));
+            }
+            RunEvent::KitsDropped { pos, side, kits } => {
+                if let Some((needed, dropped)) = victims.get_mut(&(pos, side)) {
+                    *dropped = (*dropped + kits).min(*needed);
+                }
+            }
+            RunEvent::Checkpoint(pos) => {
+                checkpoints.insert(pos);
+            }
+            RunEvent::Ramp(pos) => {
+                ramps.insert(pos);
+            }
+            RunEvent::LackOfProgress => lack_of_progress += 1,
+            RunEvent::Exit => exited = true,
+        }
+    }
+
+    let identified = victims.len() as u32;
+    let kits: u32 = victims.values().map(|(_, dropped)| *dropped as u32).sum();
+    let reliability = (identified * rules.reliability + kits * rules.reliability)
+        .saturating_sub(lack_of_progress * rules.lack_of_progress);
+    Score {
+        victims: identified * rules.victim,
+        kits: kits * rules.kit,
+        checkpoints: checkpoints.len() as u32 * rules.checkpoint,
+        ramps: ramps.len() as u32 * rules.ramp,
+        reliability,
+        exit: if exited {
+            identified * rules.exit_per_victim
+        } else {
+            0
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VICTIM: RunEvent = RunEvent::VictimIdentified {
+        pos: (1, 0, 0),
+        side: Direction::Up,
+        kind: VictimKind::S,
+    };
+
+    const fn kits(kits: u8) -> RunEvent {
+        RunEvent::KitsDropped {
+            pos: (1, 0, 0),
+            side: Direction::Up,
+            kits,
+        }
+    }
+
+    #[test]
+    fn each_rule_scores() {
+        let cases: [(&str, Vec<RunEvent>, Score); 10] = [
+            (
+                "nothing",
+                vec![RunEvent::Visited((0, 1, 0))],
+                Score::default(),
+            ),
+            (
+                "victim",
+                vec![VICTIM, VICTIM],
+                Score {
+                    victims: 5,
+                    reliability: 10,
+                    ..Score::default()
+                },
+            ),
+            (
+                "kits",
+                vec![VICTIM, kits(1), kits(2)],
+                Score {
+                    victims: 5,
+                    kits: 20,
+                    reliability: 30,
+                    ..Score::default()
+                },
+            ),
+            ("kits without a victim", vec![kits(1)], Score::default()),
+            (
+                "checkpoint",
+                vec![
+                    RunEvent::Checkpoint((0, 1, 0)),
+                    RunEvent::Checkpoint((0, 1, 0)),
+                ],
+                Score {
+                    checkpoints: 10,
+                    ..Score::default()
+                },
+            ),
+            (
+                "ramp",
+                vec![RunEvent::Ramp((2, 0, 0)), RunEvent::Ramp((2, 1, 0))],
+                Score {
+                    ramps: 40,
+                    ..Score::default()
+                },
+            ),
+            (
+                "lack of progress",
+                vec![VICTIM, kits(1), RunEvent::LackOfProgress],
+                Score {
+                    victims: 5,
+                    kits: 10,
+                    reliability: 10,
+                    ..Score::default()
+                },
+            ),
+            (
+                "reliability never goes below zero",
+                vec![VICTIM, RunEvent::LackOfProgress, RunEvent::LackOfProgress],
+                Score {
+                    victims: 5,
+                    ..Score::default()
+                },
+            ),
+            (
+                "exit",
+                vec![VICTIM, RunEvent::Exit],
+                Score {
+                    victims: 5,
+                    reliability: 10,
+                    exit: 10,
+                    ..Score::default()
+                },
+            ),
+            (
+                "exit with no victims",
+                vec![RunEvent::Exit],
+                Score::default(),
+            ),
+        ];
+        for (name, events, expected) in cases {
+            let log = RunLog { events };
+            assert_eq!(score(&log, &Rules::default()), expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn the_total_adds_every_element() {
+        let score = Score {
+            victims: 1,
+            kits: 2,
+            checkpoints: 4,
+            ramps: 8,
+            reliability: 16,
+            exit: 32,
+        };
+        assert_eq!(score.total(), 63);
+    }
+}
@@ -1,41 +1,52 @@
 #![allow(dead_code)]
+pub mod mock;
 pub mod mpu6050;
 pub mod vl6180x;
 
-use anyhow::Result;
-use rppal::i2c::I2c;
+use anyhow::{anyhow, Result};
+use embedded_hal::i2c::{Error, I2c};
 
-fn read_raw_data(i2c: &mut I2c, addr: u16) -> Result<i16> {
+// The drivers talk to any `embedded_hal` bus: rppal's `I2c` on the robot
+// (with its `hal` feature) and `mock::MockI2c` anywhere else.
+fn bus_error(err: impl Error) -> anyhow::Error {
+    anyhow!("I2C error: {:?}", err)
+}
+
+fn read_raw_data(i2c: &mut impl I2c, device: u8, addr: u16) -> Result<i16> {
     let mut reg = [0u8, 2];
-    i2c.block_read(addr as u8, &mut reg)?;
+    i2c.write_read(device, &[addr as u8], &mut reg)
+        .map_err(bus_error)?;
     Ok(((reg[0] as i16) << 8) | reg[1] as i16)
 }
 
-fn write8(i2c: &mut I2c, addr: u16, data: u8) -> Result<()> {
-    i2c.write(&[(addr >> 8) as u8 & 0xFF, addr as u8 & 0xFF, data])?;
-    Ok(())
+// The register helpers below keep the bus error, for drivers with errors of
+// their own.
+fn write8<I: I2c>(i2c: &mut I, device: u8, addr: u16, data: u8) -> Result<(), I::Error> {
+    i2c.write(device, &[(addr >> 8) as u8 & 0xFF, addr as u8 & 0xFF, data])
 }
 
-fn write16(i2c: &mut I2c, addr: u16, data: u16) -> Result<()> {
-    i2c.write(&[
-        (addr >> 8) as u8 & 0xFF,
-        addr as u8 & 0xFF,
-        (data >> 8) as u8 & 0xFF,
-        data as u8 & 0xFF,
-    ])?;
-    Ok(())
+fn write16<I: I2c>(i2c: &mut I, device: u8, addr: u16, data: u16) -> Result<(), I::Error> {
+    i2c.write(
+        device,
+        &[
+            (addr >> 8) as u8 & 0xFF,
+            addr as u8 & 0xFF,
+            (data >> 8) as u8 & 0xFF,
+            data as u8 & 0xFF,
+        ],
+    )
 }
 
-fn read8(i2c: &mut I2c, addr: u16) -> Result<u8> {
+fn read8<I: I2c>(i2c: &mut I, device: u8, addr: u16) -> Result<u8, I::Error> {
     let mut reg = [0u8; 1];
-    i2c.write(&[(addr >> 8) as u8 & 0xFF, addr as u8 & 0xFF])?;
-    i2c.read(&mut reg)?;
+    i2c.write(device, &[(addr >> 8) as u8 & 0xFF, addr as u8 & 0xFF])?;
+    i2c.read(device, &mut reg)?;
     Ok(reg[0])
 }
 
-fn read16(i2c: &mut I2c, addr: u16) -> Result<i16> {
+fn read16<I: I2c>(i2c: &mut I, device: u8, addr: u16) -> Result<i16, I::Error> {
     let mut reg = [0u8, 2];
-    i2c.write(&[(addr >> 8) as u8 & 0xFF, addr as u8 & 0xFF])?;
-    i2c.read(&mut reg)?;
+    i2c.write(device, &[(addr >> 8) as u8 & 0xFF, addr as u8 & 0xFF])?;
+    i2c.read(device, &mut reg)?;
     Ok(((reg[0] as i16) << 8) | reg[1] as i16)
 }
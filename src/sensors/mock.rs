@@ -0,0 +1,281 @@
+/*!
+An in-memory I2C bus, to run the drivers without the robot.
+
+Every address on the bus holds a [`Device`]. A write sets the register
+pointer from its first byte (two for devices with 16 bit registers, like the
+VL6180X) and writes the rest of the bytes one register after the other, a
+read goes on from the pointer. Talking to an address with nothing on it fails
+like a real bus would, with no acknowledge.
+
+[`vl6180x::VL6180XSim`] and [`mpu6050::MPU6050Sim`] behave like the chips
+do, measuring the distance and motion they are given.
+
+```rust,ignore
+use rusty_capybara::sensors::mock::{vl6180x::VL6180XSim, MockI2c};
+use rusty_capybara::sensors::vl6180x::VL6180X;
+use std::sync::{Arc, Mutex};
+
+let bus = MockI2c::new();
+let sim = Arc::new(Mutex::new(VL6180XSim::new()));
+bus.add_device(0x29, sim.clone());
+let mut tof = VL6180X::with_i2c(bus, Some(0x30));
+tof.begin()?;
+sim.lock().unwrap().set_distance(Some(80));
+let measurement = tof.range()?;
+assert!(measurement.valid());
+assert_eq!(measurement.distance, 80);
+```
+*/
+pub mod mpu6050;
+pub mod vl6180x;
+
+use embedded_hal::i2c::{Error, ErrorKind, ErrorType, I2c, NoAcknowledgeSource, Operation};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+/// Something that answers on the mock bus.
+pub trait Device: Send {
+    /// How many bytes a register address takes.
+    fn address_bytes(&self) -> usize;
+    fn read(&mut self, reg: u16) -> u8;
+    fn write(&mut self, reg: u16, value: u8);
+
+    /// The address the device answers on from now on, if it was told to
+    /// change it. Asked after every transaction.
+    fn moved_to(&mut self) -> Option<u8> {
+        None
+    }
+}
+
+/// A plain register file: reads give back what was last written or set.
+#[derive(Debug, Default)]
+pub struct Registers {
+    address_bytes: usize,
+    values: HashMap<u16, u8>,
+}
+
+impl Registers {
+    pub fn new(address_bytes: usize) -> Self {
+        Self {
+            address_bytes,
+            values: HashMap::new(),
+        }
+    }
+
+    pub fn get(&self, reg: u16) -> u8 {
+        self.values.get(&reg).copied().unwrap_or(0)
+    }
+
+    pub fn set(&mut self, reg: u16, value: u8) {
+        self.values.insert(reg, value);
+    }
+}
+
+impl Device for Registers {
+    fn address_bytes(&self) -> usize {
+        self.address_bytes
+    }
+
+    fn read(&mut self, reg: u16) -> u8 {
+        self.get(reg)
+    }
+
+    fn write(&mut self, reg: u16, value: u8) {
+        self.set(reg, value);
+    }
+}
+
+// So the caller can keep a handle on a device once it is on the bus.
+impl<D: Device> Device for Arc<Mutex<D>> {
+    fn address_bytes(&self) -> usize {
+        self.lock().unwrap().address_bytes()
+    }
+
+    fn read(&mut self, reg: u16) -> u8 {
+        self.lock().unwrap().read(reg)
+    }
+
+    fn write(&mut self, reg: u16, value: u8) {
+        self.lock().unwrap().write(reg, value)
+    }
+
+    fn moved_to(&mut self) -> Option<u8> {
+        self.lock().unwrap().moved_to()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MockError(ErrorKind);
+
+impl Error for MockError {
+    fn kind(&self) -> ErrorKind {
+        self.0
+    }
+}
+
+impl fmt::Display for MockError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for MockError {}
+
+struct Attached {
+    device: Box<dyn Device>,
+    pointer: u16,
+}
+
+impl Attached {
+    fn write(&mut self, bytes: &[u8]) {
+        let address_bytes = self.device.address_bytes().min(bytes.len());
+        let (reg, data) = bytes.split_at(address_bytes);
+        if !reg.is_empty() {
+            self.pointer = reg
+                .iter()
+                .fold(0, |pointer, byte| (pointer << 8) | *byte as u16);
+        }
+        for byte in data {
+            self.device.write(self.pointer, *byte);
+            self.pointer = self.pointer.wrapping_add(1);
+        }
+    }
+
+    fn read(&mut self, buffer: &mut [u8]) {
+        for byte in buffer {
+            *byte = self.device.read(self.pointer);
+            self.pointer = self.pointer.wrapping_add(1);
+        }
+    }
+}
+
+/// The bus. Clones share the same devices, so one can be handed to a driver
+/// while the other keeps adding or looking at devices.
+#[derive(Clone, Default)]
+pub struct MockI2c {
+    devices: Arc<Mutex<HashMap<u8, Attached>>>,
+}
+
+impl MockI2c {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Puts `device` on the bus at `address`, replacing whatever was there.
+    pub fn add_device(&self, address: u8, device: impl Device + 'static) {
+        self.devices.lock().unwrap().insert(
+            address,
+            Attached {
+                device: Box::new(device),
+                pointer: 0,
+            },
+        );
+    }
+
+    pub fn remove_device(&self, address: u8) {
+        self.devices.lock().unwrap().remove(&address);
+    }
+}
+
+impl ErrorType for MockI2c {
+    type Error = MockError;
+}
+
+impl I2c for MockI2c {
+    fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        let mut devices = self.devices.lock().unwrap();
+        let attached = devices
+            .get_mut(&address)
+            .ok_or(MockError(ErrorKind::NoAcknowledge(
+                NoAcknowledgeSource::Address,
+            )))?;
+        for operation in operations {
+            match operation {
+                Operation::Write(bytes) => attached.write(bytes),
+                Operation::Read(buffer) => attached.read(buffer),
+            }
+        }
+        if let Some(moved_to) = attached.device.moved_to() {
+            let attached = devices.remove(&address).unwrap();
+            devices.insert(moved_to, attached);
+        }
+        Ok(())
+    }
+}
+
+/// A VL6180X begun at its default address on a bus of its own, for the driver
+/// and the simulation tests.
+#[cfg(test)]
+pub(crate) fn tof_on_bus() -> (
+    crate::sensors::vl6180x::VL6180X<MockI2c>,
+    Arc<Mutex<vl6180x::VL6180XSim>>,
+) {
+    let bus = MockI2c::new();
+    let sim = Arc::new(Mutex::new(vl6180x::VL6180XSim::new()));
+    bus.add_device(0x29, sim.clone());
+    let mut tof = crate::sensors::vl6180x::VL6180X::with_i2c(bus, None);
+    tof.begin().unwrap();
+    (tof, sim)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bus_with(address_bytes: usize) -> (MockI2c, Arc<Mutex<Registers>>) {
+        let bus = MockI2c::new();
+        let registers = Arc::new(Mutex::new(Registers::new(address_bytes)));
+        bus.add_device(0x10, registers.clone());
+        (bus, registers)
+    }
+
+    #[test]
+    fn write_sets_the_register_pointer() {
+        let (mut bus, registers) = bus_with(1);
+        bus.write(0x10, &[0x05, 0xAA, 0xBB]).unwrap();
+        assert_eq!(registers.lock().unwrap().get(0x05), 0xAA);
+        assert_eq!(registers.lock().unwrap().get(0x06), 0xBB);
+
+        bus.write(0x10, &[0x06]).unwrap();
+        let mut buffer = [0; 1];
+        bus.read(0x10, &mut buffer).unwrap();
+        assert_eq!(buffer, [0xBB]);
+    }
+
+    #[test]
+    fn write_sets_a_two_byte_pointer() {
+        let (mut bus, registers) = bus_with(2);
+        bus.write(0x10, &[0x02, 0x12, 0x30]).unwrap();
+        assert_eq!(registers.lock().unwrap().get(0x212), 0x30);
+    }
+
+    #[test]
+    fn reads_go_on_from_the_pointer() {
+        let (mut bus, registers) = bus_with(1);
+        for (reg, value) in [(0x20, 1), (0x21, 2), (0x22, 3), (0x23, 4)] {
+            registers.lock().unwrap().set(reg, value);
+        }
+        let mut buffer = [0; 2];
+        bus.write_read(0x10, &[0x20], &mut buffer).unwrap();
+        assert_eq!(buffer, [1, 2]);
+        bus.read(0x10, &mut buffer).unwrap();
+        assert_eq!(buffer, [3, 4]);
+    }
+
+    #[test]
+    fn nothing_on_the_address_is_no_acknowledge() {
+        let (mut bus, _) = bus_with(1);
+        let expected = ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address);
+        let mut buffer = [0; 1];
+        assert_eq!(bus.read(0x11, &mut buffer).unwrap_err().kind(), expected);
+        assert_eq!(bus.write(0x11, &[0x00]).unwrap_err().kind(), expected);
+
+        bus.remove_device(0x10);
+        assert_eq!(bus.read(0x10, &mut buffer).unwrap_err().kind(), expected);
+    }
+}
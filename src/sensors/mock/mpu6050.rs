@@ -0,0 +1,281 @@
+use super::{Device, Registers};
+use crate::sensors::mpu6050::{
+    ACCEL_CONFIG, ACCEL_XOUT_H, GYRO_CONFIG, GYRO_ZOUT_H, PWR_MGMT_1, WHO_AM_I,
+};
+
+// PWR_MGMT_1 bits.
+const DEVICE_RESET: u8 = 0x80;
+const SLEEP: u8 = 0x40;
+
+const GYRO_ZOUT_L: u16 = GYRO_ZOUT_H + 1;
+
+/**
+An MPU6050 on the mock bus, measuring whatever motion it is given.
+
+It starts out of reset, asleep and reading zeros until `PWR_MGMT_1` wakes it
+up. Then the accel, temperature and gyro registers hold the given values in
+the full scale ranges set in `ACCEL_CONFIG` and `GYRO_CONFIG`, saturating at
+their ends like the chip does.
+*/
+pub struct MPU6050Sim {
+    registers: Registers,
+    acceleration: [f32; 3],
+    rotation: [f32; 3],
+    temperature: f32,
+}
+
+impl Default for MPU6050Sim {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MPU6050Sim {
+    /// Lying flat and still, at 25 °C.
+    pub fn new() -> Self {
+        Self {
+            registers: Self::reset_registers(),
+            acceleration: [0.0, 0.0, 1.0],
+            rotation: [0.0; 3],
+            temperature: 25.0,
+        }
+    }
+
+    fn reset_registers() -> Registers {
+        let mut registers = Registers::new(1);
+        registers.set(PWR_MGMT_1, SLEEP);
+        registers.set(WHO_AM_I, 0x68);
+        registers
+    }
+
+    /// The acceleration along x, y and z in g.
+    pub fn set_acceleration(&mut self, acceleration: [f32; 3]) {
+        self.acceleration = acceleration;
+    }
+
+    /// The angular speed around x, y and z in degrees per second.
+    pub fn set_rotation(&mut self, rotation: [f32; 3]) {
+        self.rotation = rotation;
+    }
+
+    /// The temperature of the chip in °C.
+    pub fn set_temperature(&mut self, temperature: f32) {
+        self.temperature = temperature;
+    }
+
+    pub fn sleeping(&self) -> bool {
+        self.registers.get(PWR_MGMT_1) & SLEEP != 0
+    }
+
+    /// The value the driver last wrote to `reg`, or the chip set there.
+    pub fn register(&self, reg: u16) -> u8 {
+        self.registers.get(reg)
+    }
+
+    // The words from ACCEL_XOUT_H on: accel x, y, z, temperature, gyro x, y, z.
+    fn measurements(&self) -> [i16; 7] {
+        let accel_scale = 16384.0 / (1 << ((self.registers.get(ACCEL_CONFIG) >> 3) & 0x03)) as f32;
+        let gyro_scale = 131.0 / (1 << ((self.registers.get(GYRO_CONFIG) >> 3) & 0x03)) as f32;
+        let raw = |value: f32| value.round().clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+        [
+            raw(self.acceleration[0] * accel_scale),
+            raw(self.acceleration[1] * accel_scale),
+            raw(self.acceleration[2] * accel_scale),
+            raw((self.temperature - 36.53) * 340.0),
+            raw(self.rotation[0] * gyro_scale),
+            raw(self.rotation[1] * gyro_scale),
+            raw(self.rotation[2] * gyro_scale),
+        ]
+    }
+}
+
+impl Device for MPU6050Sim {
+    fn address_bytes(&self) -> usize {
+        1
+    }
+
+    fn read(&mut self, reg: u16) -> u8 {
+        match reg {
+            ACCEL_XOUT_H..=GYRO_ZOUT_L if !self.sleeping() => {
+                let offset = (reg - ACCEL_XOUT_H) as usize;
+                let word = self.measurements()[offset / 2].to_be_bytes();
+                word[offset % 2]
+            }
+            _ => self.registers.get(reg),
+        }
+    }
+
+    fn write(&mut self, reg: u16, value: u8) {
+        match reg {
+            WHO_AM_I => {}
+            PWR_MGMT_1 if value & DEVICE_RESET != 0 => self.registers = Self::reset_registers(),
+            _ => self.registers.set(reg, value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sensors::mock::MockI2c;
+    use crate::sensors::mpu6050::{ADDR, MPU6050, TEMP_OUT_H};
+    use crate::sensors::read_raw_data;
+    use embedded_hal::i2c::I2c;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    fn mpu_on_bus() -> (MPU6050<MockI2c>, MockI2c, Arc<Mutex<MPU6050Sim>>) {
+        let bus = MockI2c::new();
+        let sim = Arc::new(Mutex::new(MPU6050Sim::new()));
+        bus.add_device(ADDR as u8, sim.clone());
+        let mpu = MPU6050::with_i2c(bus.clone()).unwrap();
+        (mpu, bus, sim)
+    }
+
+    fn word(bus: &mut MockI2c, reg: u16) -> i16 {
+        read_raw_data(bus, ADDR as u8, reg).unwrap()
+    }
+
+    fn write(bus: &mut MockI2c, reg: u16, value: u8) {
+        bus.write(ADDR as u8, &[reg as u8, value]).unwrap();
+    }
+
+    #[test]
+    fn init_wakes_the_chip_up() {
+        let (_mpu, mut bus, sim) = mpu_on_bus();
+        assert!(!sim.lock().unwrap().sleeping());
+        assert_eq!(sim.lock().unwrap().register(WHO_AM_I), 0x68);
+        assert_eq!(word(&mut bus, ACCEL_XOUT_H + 4), 16384);
+    }
+
+    #[test]
+    fn temperature_is_offset_and_scaled() {
+        let (_mpu, mut bus, sim) = mpu_on_bus();
+        sim.lock().unwrap().set_temperature(36.53);
+        assert_eq!(word(&mut bus, TEMP_OUT_H), 0);
+        sim.lock().unwrap().set_temperature(46.53);
+        assert_eq!(word(&mut bus, TEMP_OUT_H), 3400);
+        sim.lock().unwrap().set_temperature(26.53);
+        assert_eq!(word(&mut bus, TEMP_OUT_H), -3400);
+    }
+
+    #[test]
+    fn acceleration_follows_the_full_scale_range() {
+        let (_mpu, mut bus, sim) = mpu_on_bus();
+        sim.lock().unwrap().set_acceleration([0.5, -1.0, 1.5]);
+        assert_eq!(word(&mut bus, ACCEL_XOUT_H), 8192);
+        assert_eq!(word(&mut bus, ACCEL_XOUT_H + 2), -16384);
+        assert_eq!(word(&mut bus, ACCEL_XOUT_H + 4), 24576);
+
+        // ±16 g.
+        write(&mut bus, ACCEL_CONFIG, 0x18);
+        assert_eq!(word(&mut bus, ACCEL_XOUT_H), 1024);
+        assert_eq!(word(&mut bus, ACCEL_XOUT_H + 2), -2048);
+
+        // Back to ±2 g, where 3 g saturates.
+        write(&mut bus, ACCEL_CONFIG, 0x00);
+        sim.lock().unwrap().set_acceleration([3.0, -3.0, 0.0]);
+        assert_eq!(word(&mut bus, ACCEL_XOUT_H), i16::MAX);
+        assert_eq!(word(&mut bus, ACCEL_XOUT_H + 2), i16::MIN);
+    }
+
+    #[test]
+    fn rotation_follows_the_full_scale_range() {
+        let (_mpu, mut bus, sim) = mpu_on_bus();
+        sim.lock().unwrap().set_rotation([10.0, -20.0, 300.0]);
+        assert_eq!(word(&mut bus, GYRO_ZOUT_H - 4), 1310);
+        assert_eq!(word(&mut bus, GYRO_ZOUT_H - 2), -2620);
+        // 300 °/s is past ±250 °/s.
+        assert_eq!(word(&mut bus, GYRO_ZOUT_H), i16::MAX);
+
+        // ±2000 °/s.
+        write(&mut bus, GYRO_CONFIG, 0x18);
+        assert_eq!(word(&mut bus, GYRO_ZOUT_H - 2), -328);
+        assert_eq!(word(&mut bus, GYRO_ZOUT_H), 4913);
+    }
+
+    #[test]
+    fn sleeping_and_reset_chips_read_zeros() {
+        let (_mpu, mut bus, sim) = mpu_on_bus();
+        write(&mut bus, ACCEL_CONFIG, 0x08);
+        assert_eq!(word(&mut bus, ACCEL_XOUT_H + 4), 8192);
+
+        write(&mut bus, PWR_MGMT_1, SLEEP);
+        assert!(sim.lock().unwrap().sleeping());
+        assert_eq!(word(&mut bus, ACCEL_XOUT_H + 4), 0);
+        write(&mut bus, PWR_MGMT_1, 0x00);
+        assert_eq!(word(&mut bus, ACCEL_XOUT_H + 4), 8192);
+
+        // WHO_AM_I is read only, and a reset puts the ranges back.
+        write(&mut bus, WHO_AM_I, 0x00);
+        write(&mut bus, PWR_MGMT_1, DEVICE_RESET);
+        let chip = sim.lock().unwrap();
+        assert!(chip.sleeping());
+        assert_eq!(chip.register(WHO_AM_I), 0x68);
+        assert_eq!(chip.register(ACCEL_CONFIG), 0x00);
+        drop(chip);
+        assert_eq!(word(&mut bus, ACCEL_XOUT_H + 4), 0);
+    }
+
+    #[test]
+    fn roll_and_pitch_follow_the_tilt() {
+        let (mut mpu, _bus, sim) = mpu_on_bus();
+        let mut filter = mpu.filter(10).unwrap();
+
+        // Tilted 30° around x and turning at 30 °/s for a second, so the
+        // gyro and the accelerometer agree.
+        let (sin, cos) = 30f32.to_radians().sin_cos();
+        sim.lock().unwrap().set_acceleration([0.0, sin, cos]);
+        sim.lock().unwrap().set_rotation([30.0, 0.0, 0.0]);
+        filter.step(Duration::from_secs(1)).unwrap();
+        assert!((mpu.get_roll() - 30.0).abs() < 0.1, "{}", mpu.get_roll());
+        assert!(mpu.get_pitch().abs() < 0.1, "{}", mpu.get_pitch());
+
+        // The same around y, with the roll held.
+        sim.lock().unwrap().set_acceleration([-sin, 0.0, cos]);
+        sim.lock().unwrap().set_rotation([0.0, 30.0, 0.0]);
+        filter.step(Duration::from_secs(1)).unwrap();
+        assert!((mpu.get_pitch() - 30.0).abs() < 0.1, "{}", mpu.get_pitch());
+        assert!((mpu.get_roll() - 29.4).abs() < 0.1, "{}", mpu.get_roll());
+    }
+
+    #[test]
+    fn yaw_integrates_the_rotation() {
+        let (mut mpu, _bus, sim) = mpu_on_bus();
+        let mut filter = mpu.filter(10).unwrap();
+        filter.step(Duration::from_millis(10)).unwrap();
+        assert_eq!(mpu.get_yaw(), 0.0);
+
+        // The first step at 90 °/s only counts half, averaging with the still one.
+        sim.lock().unwrap().set_rotation([0.0, 0.0, 90.0]);
+        filter.step(Duration::from_millis(500)).unwrap();
+        assert!((mpu.get_yaw() - 22.5).abs() < 0.1, "{}", mpu.get_yaw());
+        filter.step(Duration::from_millis(500)).unwrap();
+        assert!((mpu.get_yaw() - 67.5).abs() < 0.1, "{}", mpu.get_yaw());
+
+        sim.lock().unwrap().set_rotation([0.0; 3]);
+        filter.step(Duration::from_millis(500)).unwrap();
+        filter.step(Duration::from_secs(10)).unwrap();
+        assert!((mpu.get_yaw() - 90.0).abs() < 0.1, "{}", mpu.get_yaw());
+    }
+
+    #[test]
+    fn yaw_wraps_around_at_a_full_turn() {
+        let (mut mpu, _bus, sim) = mpu_on_bus();
+        let mut filter = mpu.filter(10).unwrap();
+        sim.lock().unwrap().set_rotation([0.0, 0.0, 200.0]);
+        filter.step(Duration::ZERO).unwrap();
+        filter.step(Duration::from_secs(2)).unwrap();
+        assert!((mpu.get_yaw() - 40.0).abs() < 0.1, "{}", mpu.get_yaw());
+    }
+
+    #[test]
+    fn calibration_removes_the_gyro_drift() {
+        let (mut mpu, _bus, sim) = mpu_on_bus();
+        sim.lock().unwrap().set_rotation([0.0, 0.0, 2.0]);
+        let mut filter = mpu.filter(10).unwrap();
+        filter.step(Duration::from_secs(1)).unwrap();
+        filter.step(Duration::from_secs(1)).unwrap();
+        assert!(mpu.get_yaw().abs() < 0.1, "{}", mpu.get_yaw());
+    }
+}
@@ -0,0 +1,382 @@
+use super::{Device, Registers};
+use crate::sensors::vl6180x::{
+    IDENTIFICATION_MODEL_ID, RESULT_ALS_STATUS, RESULT_ALS_VAL, RESULT_INTERRUPT_STATUS_GPIO,
+    RESULT_RANGE_STATUS, RESULT_RANGE_VAL, SYSALS_ANALOGUE_GAIN, SYSALS_INTEGRATION_PERIOD,
+    SYSALS_START, SYSRANGE_PART_TO_PART_RANGE_OFFSET, SYSRANGE_START, SYSTEM_CHANGE_ADDRESS,
+    SYSTEM_FRESH_OUT_OF_RESET, SYSTEM_INTERRUPT_CLEAR, SYSTEM_INTERRUPT_CONFIG,
+};
+
+const MODEL_ID: u8 = 0xB4;
+
+// Range error codes, the high nibble of RESULT_RANGE_STATUS.
+const NO_ERROR: u8 = 0;
+const EARLY_CONVERGENCE_ESTIMATE: u8 = 6;
+const RANGING_UNDERFLOW: u8 = 14;
+const RANGING_OVERFLOW: u8 = 15;
+
+// The "new sample ready" setting of the interrupt config and status.
+const NEW_SAMPLE_READY: u8 = 0x04;
+
+// Gain for each code of SYSALS_ANALOGUE_GAIN.
+const ALS_GAINS: [f32; 8] = [20.0, 10.0, 5.0, 2.5, 1.67, 1.25, 1.0, 40.0];
+const LUX_PER_COUNT: f32 = 0.32;
+
+// One kind of measurement, range or ambient light, started through its own
+// start register. The methods that can end a measurement return whether they
+// did, for the result to be filled in.
+#[derive(Default)]
+struct Channel {
+    // Polls left before the measurement going on is done.
+    measuring: Option<u32>,
+    continuous: bool,
+    ready: bool,
+}
+
+impl Channel {
+    fn start(&mut self, latency: u32) -> bool {
+        self.measuring = Some(latency);
+        latency == 0
+    }
+
+    fn poll(&mut self) -> bool {
+        match self.measuring {
+            Some(left) if left <= 1 => true,
+            Some(left) => {
+                self.measuring = Some(left - 1);
+                false
+            }
+            None => false,
+        }
+    }
+
+    fn start_stop(&mut self, value: u8, latency: u32) -> bool {
+        if value & 0x01 == 0 {
+            return false;
+        }
+        if self.continuous {
+            self.continuous = false;
+            self.measuring = None;
+            false
+        } else {
+            self.continuous = value & 0x02 != 0;
+            self.start(latency)
+        }
+    }
+
+    fn clear(&mut self, latency: u32) -> bool {
+        self.ready = false;
+        self.continuous && self.start(latency)
+    }
+
+    fn finish(&mut self) {
+        self.measuring = None;
+        self.ready = true;
+    }
+
+    fn device_ready(&self) -> bool {
+        !self.continuous && self.measuring.is_none()
+    }
+
+    // Its bits of RESULT_INTERRUPT_STATUS_GPIO, for its bits of the config.
+    fn interrupt_status(&self, config: u8) -> u8 {
+        if self.ready && config == NEW_SAMPLE_READY {
+            NEW_SAMPLE_READY
+        } else {
+            0
+        }
+    }
+}
+
+/**
+A VL6180X on the mock bus, ranging whatever distance it is given and reading
+whatever light.
+
+It starts out of reset on the default address. Range and ambient light each
+take a single measurement when their start register is written and continuous
+ones until they are stopped. A measurement is done once a status has been
+polled `latency` times, then the result shows in the result registers and, if
+the interrupt is configured as new sample ready, in
+`RESULT_INTERRUPT_STATUS_GPIO` until it is cleared.
+*/
+pub struct VL6180XSim {
+    registers: Registers,
+    distance: Option<u16>,
+    error: Option<u8>,
+    lux: f32,
+    latency: u32,
+    range: Channel,
+    als: Channel,
+    moved_to: Option<u8>,
+}
+
+impl Default for VL6180XSim {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl VL6180XSim {
+    pub fn new() -> Self {
+        let mut registers = Registers::new(2);
+        registers.set(IDENTIFICATION_MODEL_ID, MODEL_ID);
+        registers.set(SYSTEM_FRESH_OUT_OF_RESET, 0x01);
+        Self {
+            registers,
+            distance: None,
+            error: None,
+            lux: 0.0,
+            latency: 0,
+            range: Channel::default(),
+            als: Channel::default(),
+            moved_to: None,
+        }
+    }
+
+    /// The distance to the target in mm, `None` if there is nothing in front
+    /// of the sensor. Taken by the next measurement.
+    pub fn set_distance(&mut self, distance: Option<u16>) {
+        self.distance = distance;
+    }
+
+    /// Makes the next measurements fail with this error code whatever the
+    /// distance, like 1 for a broken VCSEL. `None` goes back to ranging.
+    pub fn set_error(&mut self, code: Option<u8>) {
+        self.error = code;
+    }
+
+    /// The ambient light in lux. Taken by the next measurement.
+    pub fn set_lux(&mut self, lux: f32) {
+        self.lux = lux;
+    }
+
+    /// How many status polls a measurement takes.
+    pub fn set_latency(&mut self, polls: u32) {
+        self.latency = polls;
+    }
+
+    pub fn continuous(&self) -> bool {
+        self.range.continuous
+    }
+
+    pub fn als_continuous(&self) -> bool {
+        self.als.continuous
+    }
+
+    /// The value the driver last wrote to `reg`, or the chip set there.
+    pub fn register(&self, reg: u16) -> u8 {
+        self.registers.get(reg)
+    }
+
+    fn poll(&mut self) {
+        if self.range.poll() {
+            self.finish_range();
+        }
+        if self.als.poll() {
+            self.finish_als();
+        }
+    }
+
+    fn finish_range(&mut self) {
+        self.range.finish();
+        let offset = self.registers.get(SYSRANGE_PART_TO_PART_RANGE_OFFSET) as i8 as i32;
+        let (error, range) = match self.distance.map(|distance| distance as i32 + offset) {
+            None => (EARLY_CONVERGENCE_ESTIMATE, 255),
+            Some(range) if range < 0 => (RANGING_UNDERFLOW, 0),
+            Some(range) if range > 255 => (RANGING_OVERFLOW, 255),
+            Some(range) => (NO_ERROR, range as u8),
+        };
+        let error = self.error.unwrap_or(error);
+        self.registers.set(RESULT_RANGE_VAL, range);
+        self.registers.set(RESULT_RANGE_STATUS, error << 4);
+    }
+
+    fn finish_als(&mut self) {
+        self.als.finish();
+        let gain = ALS_GAINS[(self.registers.get(SYSALS_ANALOGUE_GAIN) & 0x07) as usize];
+        let period = ((self.registers.get(SYSALS_INTEGRATION_PERIOD) as u16) << 8
+            | self.registers.get(SYSALS_INTEGRATION_PERIOD + 1) as u16)
+            & 0x1FF;
+        let count = self.lux / LUX_PER_COUNT * gain * (period + 1) as f32 / 100.0;
+        let count = count.round().clamp(0.0, u16::MAX as f32) as u16;
+        let [high, low] = count.to_be_bytes();
+        self.registers.set(RESULT_ALS_VAL, high);
+        self.registers.set(RESULT_ALS_VAL + 1, low);
+    }
+
+    fn interrupt_status(&self) -> u8 {
+        let config = self.registers.get(SYSTEM_INTERRUPT_CONFIG);
+        self.range.interrupt_status(config & 0x07)
+            | self.als.interrupt_status((config >> 3) & 0x07) << 3
+    }
+}
+
+impl Device for VL6180XSim {
+    fn address_bytes(&self) -> usize {
+        2
+    }
+
+    fn read(&mut self, reg: u16) -> u8 {
+        match reg {
+            RESULT_RANGE_STATUS => {
+                self.poll();
+                self.registers.get(reg) | self.range.device_ready() as u8
+            }
+            RESULT_ALS_STATUS => {
+                self.poll();
+                self.als.device_ready() as u8
+            }
+            RESULT_INTERRUPT_STATUS_GPIO => {
+                self.poll();
+                self.interrupt_status()
+            }
+            _ => self.registers.get(reg),
+        }
+    }
+
+    fn write(&mut self, reg: u16, value: u8) {
+        let latency = self.latency;
+        match reg {
+            // Read only.
+            IDENTIFICATION_MODEL_ID
+            | RESULT_RANGE_STATUS
+            | RESULT_RANGE_VAL
+            | RESULT_ALS_STATUS
+            | RESULT_ALS_VAL => {}
+            SYSRANGE_START => {
+                self.registers.set(reg, value & 0x02);
+                if self.range.start_stop(value, latency) {
+                    self.finish_range();
+                }
+            }
+            SYSALS_START => {
+                self.registers.set(reg, value & 0x02);
+                if self.als.start_stop(value, latency) {
+                    self.finish_als();
+                }
+            }
+            SYSTEM_INTERRUPT_CLEAR => {
+                if value & 0x01 != 0 && self.range.clear(latency) {
+                    self.finish_range();
+                }
+                if value & 0x02 != 0 && self.als.clear(latency) {
+                    self.finish_als();
+                }
+            }
+            SYSTEM_CHANGE_ADDRESS => {
+                self.registers.set(reg, value & 0x7F);
+                self.moved_to = Some(value & 0x7F);
+            }
+            _ => self.registers.set(reg, value),
+        }
+    }
+
+    fn moved_to(&mut self) -> Option<u8> {
+        self.moved_to.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sensors::mock::{tof_on_bus, MockError, MockI2c};
+    use crate::sensors::vl6180x::{Error, Measurement, RangeStatus, VL6180X};
+    use embedded_hal::i2c::I2c;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn begin_moves_the_sensor_to_its_address() {
+        let mut bus = MockI2c::new();
+        let sim = Arc::new(Mutex::new(VL6180XSim::new()));
+        bus.add_device(0x29, sim.clone());
+        let mut tof = VL6180X::with_i2c(bus.clone(), Some(0x30));
+        tof.begin().unwrap();
+
+        assert_eq!(sim.lock().unwrap().register(SYSTEM_CHANGE_ADDRESS), 0x30);
+        assert_eq!(sim.lock().unwrap().register(SYSTEM_FRESH_OUT_OF_RESET), 0);
+        assert!(bus.read(0x29, &mut [0]).is_err());
+        assert!(bus.read(0x30, &mut [0]).is_ok());
+
+        // Already moved, so a second begin finds it where it is.
+        tof.begin().unwrap();
+        sim.lock().unwrap().set_distance(Some(80));
+        assert_eq!(tof.range().unwrap().distance, 80);
+    }
+
+    #[test]
+    fn single_range_waits_for_the_result() {
+        let (mut tof, sim) = tof_on_bus();
+        sim.lock().unwrap().set_latency(5);
+        sim.lock().unwrap().set_distance(Some(80));
+        assert_eq!(
+            tof.range().unwrap(),
+            Measurement {
+                distance: 80,
+                status: RangeStatus::Valid,
+            }
+        );
+        sim.lock().unwrap().set_distance(Some(120));
+        assert_eq!(tof.range().unwrap().distance, 120);
+        assert!(!sim.lock().unwrap().continuous());
+    }
+
+    #[test]
+    fn continuous_range_follows_the_distance() {
+        let (mut tof, sim) = tof_on_bus();
+        sim.lock().unwrap().set_latency(3);
+        sim.lock().unwrap().set_distance(Some(50));
+        tof.start_range_continuous(100).unwrap();
+        assert!(sim.lock().unwrap().continuous());
+        assert_eq!(tof.range().unwrap().distance, 50);
+
+        sim.lock().unwrap().set_distance(Some(60));
+        assert_eq!(tof.range().unwrap().distance, 60);
+        assert!(sim.lock().unwrap().continuous());
+
+        tof.stop_range_continuous().unwrap();
+        assert!(!sim.lock().unwrap().continuous());
+    }
+
+    #[test]
+    fn try_range_does_not_wait() {
+        let (mut tof, sim) = tof_on_bus();
+        sim.lock().unwrap().set_latency(3);
+        sim.lock().unwrap().set_distance(Some(80));
+
+        let mut tries = 1;
+        let mut measurement = tof.try_range().unwrap();
+        assert_eq!(measurement, None);
+        while measurement.is_none() {
+            assert!(tries < 10, "No result after {} tries", tries);
+            measurement = tof.try_range().unwrap();
+            tries += 1;
+        }
+        assert_eq!(measurement.unwrap().distance, 80);
+        assert!(tries > 2);
+    }
+
+    #[test]
+    fn errors_show_in_the_status() {
+        let (mut tof, sim) = tof_on_bus();
+        sim.lock().unwrap().set_distance(None);
+        let measurement = tof.range().unwrap();
+        assert_eq!(measurement.status, RangeStatus::EarlyConvergenceEstimate);
+        assert!(measurement.status.out_of_range());
+
+        sim.lock().unwrap().set_distance(Some(300));
+        assert_eq!(tof.range().unwrap().status, RangeStatus::RangingOverflow);
+
+        sim.lock().unwrap().set_distance(Some(80));
+        sim.lock().unwrap().set_error(Some(1));
+        let measurement = tof.range().unwrap();
+        assert_eq!(measurement.status, RangeStatus::VcselContinuity);
+        assert!(measurement.status.glitch());
+        assert_eq!(
+            measurement.valid_distance::<MockError>(),
+            Err(Error::Range(RangeStatus::VcselContinuity))
+        );
+
+        sim.lock().unwrap().set_error(None);
+        let measurement = tof.range().unwrap();
+        assert_eq!(measurement.valid_distance::<MockError>(), Ok(80));
+    }
+}
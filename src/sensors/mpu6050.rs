@@ -35,12 +35,14 @@ fn main() {
 
 # Note
 
-This implementation uses the `rppal` crate for I2C communication and error handling.
-Make sure to add `rppal` as a dependency in your `Cargo.toml` file.
+This implementation talks to the sensor through the `embedded-hal` I2C trait.
+`MPU6050::new` opens the bus with the `rppal` crate, which implements it with its `hal` feature,
+while `MPU6050::with_i2c` takes any other bus, like `sensors::mock::MockI2c`.
 
 ```toml
 [dependencies]
-rppal = "0.17.1"
+rppal = { version = "0.17.1", features = ["hal"] }
+embedded-hal = "1.0.0"
 ```
 
 The MPU6050 sensor must be connected to the I2C bus of the Raspberry Pi.
@@ -56,38 +58,43 @@ It is important to properly handle synchronization and ensure thread safety when
 The `MPU6050` struct provides methods to get the roll, pitch, and yaw angles, which internally lock the data using a mutex.
 It is recommended to use these methods to access the sensor data in a thread-safe manner.
 */
-use super::read_raw_data;
+use super::{bus_error, read_raw_data};
 use anyhow::Result;
+use embedded_hal::i2c::I2c as Bus;
 use rppal::i2c::I2c;
 use std::f32::consts::PI;
 use std::sync::{Arc, Mutex};
-
-const ADDR: u16 = 0x68;
-const PWR_MGMT_1: u16 = 0x6B;
-const SMPLRT_DIV: u16 = 0x19;
-const CONFIG: u16 = 0x1A;
-const GYRO_CONFIG: u16 = 0x1B;
-const INT_ENABLE: u16 = 0x38;
-const ACCEL_XOUT_H: u16 = 0x3B;
-const ACCEL_YOUT_H: u16 = 0x3D;
-const ACCEL_ZOUT_H: u16 = 0x3F;
-const GYRO_XOUT_H: u16 = 0x43;
-const GYRO_YOUT_H: u16 = 0x45;
-const GYRO_ZOUT_H: u16 = 0x47;
+use std::time::{Duration, Instant};
+
+pub(super) const ADDR: u16 = 0x68;
+pub(super) const PWR_MGMT_1: u16 = 0x6B;
+pub(super) const SMPLRT_DIV: u16 = 0x19;
+pub(super) const CONFIG: u16 = 0x1A;
+pub(super) const GYRO_CONFIG: u16 = 0x1B;
+pub(super) const ACCEL_CONFIG: u16 = 0x1C;
+pub(super) const INT_ENABLE: u16 = 0x38;
+pub(super) const ACCEL_XOUT_H: u16 = 0x3B;
+pub(super) const ACCEL_YOUT_H: u16 = 0x3D;
+pub(super) const ACCEL_ZOUT_H: u16 = 0x3F;
+pub(super) const TEMP_OUT_H: u16 = 0x41;
+pub(super) const GYRO_XOUT_H: u16 = 0x43;
+pub(super) const GYRO_YOUT_H: u16 = 0x45;
+pub(super) const GYRO_ZOUT_H: u16 = 0x47;
+pub(super) const WHO_AM_I: u16 = 0x75;
 
 /**
 The MPU6050 struct represents the MPU6050 sensor.
 It stores values of the angles on all axis.
 */
-pub struct MPU6050 {
-    i2c: Arc<Mutex<I2c>>,
+pub struct MPU6050<I = I2c> {
+    i2c: Arc<Mutex<I>>,
     roll: Arc<Mutex<f32>>,
     pitch: Arc<Mutex<f32>>,
     yaw: Arc<Mutex<f32>>,
     running: Arc<Mutex<bool>>,
 }
 
-impl MPU6050 {
+impl MPU6050<I2c> {
     /**
     Creates a new MPU6050 sensor instance on the specified I2C bus.
     # Arguments
@@ -115,9 +122,16 @@ impl MPU6050 {
     ```
     */
     pub fn new(bus: u8) -> Result<MPU6050> {
-        let i2c = Arc::new(Mutex::new(I2c::with_bus(bus)?));
+        Self::with_i2c(I2c::with_bus(bus)?)
+    }
+}
+
+impl<I: Bus + Send + 'static> MPU6050<I> {
+    /// Creates and initializes the sensor on any bus, like
+    /// `sensors::mock::MockI2c`.
+    pub fn with_i2c(i2c: I) -> Result<Self> {
         let mut mpu = MPU6050 {
-            i2c,
+            i2c: Arc::new(Mutex::new(i2c)),
             roll: Arc::new(Mutex::new(0.0)),
             pitch: Arc::new(Mutex::new(0.0)),
             yaw: Arc::new(Mutex::new(0.0)),
@@ -159,56 +173,19 @@ impl MPU6050 {
     Make sure to enable the I2C bus before running the program.
     */
     pub fn run(&mut self) -> Result<()> {
-        let i2c = self.i2c.clone();
-        let roll = self.roll.clone();
-        let pitch = self.pitch.clone();
-        let yaw = self.yaw.clone();
+        let mut filter = self.filter(500)?;
         let running = self.running.clone();
 
-        let (acc_x_err, acc_y_err, _acc_z_err, gyro_x_err, gyro_y_err, gyro_z_err) =
-            self.calculate_error(500)?;
-
         std::thread::spawn(move || -> Result<()> {
-            let mut previous_time = std::time::Instant::now();
-            let mut gyro_angle_x = 0.0;
-            let mut gyro_angle_y = 0.0;
-            *roll.lock().unwrap() = 0.0;
-            *pitch.lock().unwrap() = 0.0;
-            *yaw.lock().unwrap() = 0.0;
+            let mut previous_time = Instant::now();
             *running.lock().unwrap() = true;
-            let mut last_yaw_rate = 0.0;
 
             while *running.lock().unwrap() {
-                let acc_x = read_raw_data(&mut i2c.lock().unwrap(), ACCEL_XOUT_H)? as f32 / 16384.0;
-                let acc_y = read_raw_data(&mut i2c.lock().unwrap(), ACCEL_YOUT_H)? as f32 / 16384.0;
-                let acc_z = read_raw_data(&mut i2c.lock().unwrap(), ACCEL_ZOUT_H)? as f32 / 16384.0;
-
-                let acc_angle_x = (acc_y / (acc_x.powi(2) + acc_z.powi(2)).sqrt()).atan() * 180.0
-                    / PI
-                    - acc_x_err;
-                let acc_angle_y =
-                    (-(acc_x / (acc_y.powi(2) + acc_z.powi(2)).sqrt()).atan() * 180.0 / PI)
-                        - acc_y_err;
-
-                let gyro_x = read_raw_data(&mut i2c.lock().unwrap(), GYRO_XOUT_H)? as f32 / 131.0;
-                let gyro_y = read_raw_data(&mut i2c.lock().unwrap(), GYRO_YOUT_H)? as f32 / 131.0;
-                let gyro_z = read_raw_data(&mut i2c.lock().unwrap(), GYRO_ZOUT_H)? as f32 / 131.0;
-
-                let elapsed_time = previous_time.elapsed().as_secs_f32();
-                previous_time = std::time::Instant::now();
+                let now = Instant::now();
+                filter.step(now - previous_time)?;
+                previous_time = now;
 
-                gyro_angle_x += (gyro_x - gyro_x_err) * elapsed_time;
-                gyro_angle_y += (gyro_y - gyro_y_err) * elapsed_time;
-
-                *yaw.lock().unwrap() += (gyro_z - gyro_z_err + last_yaw_rate) * 0.5 * elapsed_time;
-                last_yaw_rate = gyro_z - gyro_z_err;
-
-                *yaw.lock().unwrap() %= 360.0;
-
-                *roll.lock().unwrap() = 0.98 * gyro_angle_x + 0.02 * acc_angle_x;
-                *pitch.lock().unwrap() = 0.98 * gyro_angle_y + 0.02 * acc_angle_y;
-
-                std::thread::sleep(std::time::Duration::from_millis(5));
+                std::thread::sleep(Duration::from_millis(5));
             }
             Ok(())
         });
@@ -216,6 +193,31 @@ impl MPU6050 {
         Ok(())
     }
 
+    /// Calibrates on `samples` readings and zeroes the angles, ready for
+    /// `run` to step the filter with the time between readings.
+    pub(super) fn filter(&mut self, samples: i32) -> Result<Filter<I>> {
+        let (acc_x_err, acc_y_err, _acc_z_err, gyro_x_err, gyro_y_err, gyro_z_err) =
+            self.calculate_error(samples)?;
+        *self.roll.lock().unwrap() = 0.0;
+        *self.pitch.lock().unwrap() = 0.0;
+        *self.yaw.lock().unwrap() = 0.0;
+
+        Ok(Filter {
+            i2c: self.i2c.clone(),
+            roll: self.roll.clone(),
+            pitch: self.pitch.clone(),
+            yaw: self.yaw.clone(),
+            acc_x_err,
+            acc_y_err,
+            gyro_x_err,
+            gyro_y_err,
+            gyro_z_err,
+            gyro_angle_x: 0.0,
+            gyro_angle_y: 0.0,
+            last_yaw_rate: 0.0,
+        })
+    }
+
     /**
     Gets the roll angle in degrees.
     The roll angle represents the rotation around the x-axis.
@@ -332,28 +334,17 @@ impl MPU6050 {
     }
 
     fn init(&mut self) -> Result<()> {
-        self.i2c.lock().unwrap().set_slave_address(ADDR)?;
-
-        self.i2c
-            .lock()
-            .unwrap()
-            .smbus_write_byte(PWR_MGMT_1 as u8, 0x00)?;
-        self.i2c
-            .lock()
-            .unwrap()
-            .smbus_write_byte(SMPLRT_DIV as u8, 0x07)?;
-        self.i2c
-            .lock()
-            .unwrap()
-            .smbus_write_byte(CONFIG as u8, 0x06)?;
-        self.i2c
-            .lock()
-            .unwrap()
-            .smbus_write_byte(GYRO_CONFIG as u8, 0x00)?;
-        self.i2c
-            .lock()
-            .unwrap()
-            .smbus_write_byte(INT_ENABLE as u8, 0x01)?;
+        let mut i2c = self.i2c.lock().unwrap();
+        for (reg, value) in [
+            (PWR_MGMT_1, 0x00),
+            (SMPLRT_DIV, 0x07),
+            (CONFIG, 0x06),
+            (GYRO_CONFIG, 0x00),
+            (INT_ENABLE, 0x01),
+        ] {
+            i2c.write(ADDR as u8, &[reg as u8, value])
+                .map_err(bus_error)?;
+        }
 
         Ok(())
     }
@@ -388,12 +379,24 @@ impl MPU6050 {
         let mut gyro_z = 0.0;
 
         for _ in 0..samples {
-            acc_x += read_raw_data(&mut self.i2c.lock().unwrap(), ACCEL_XOUT_H)? as f32 / 16384.0;
-            acc_y += read_raw_data(&mut self.i2c.lock().unwrap(), ACCEL_YOUT_H)? as f32 / 16384.0;
-            acc_z += read_raw_data(&mut self.i2c.lock().unwrap(), ACCEL_ZOUT_H)? as f32 / 16384.0;
-            gyro_x += read_raw_data(&mut self.i2c.lock().unwrap(), GYRO_XOUT_H)? as f32 / 131.0;
-            gyro_y += read_raw_data(&mut self.i2c.lock().unwrap(), GYRO_YOUT_H)? as f32 / 131.0;
-            gyro_z += read_raw_data(&mut self.i2c.lock().unwrap(), GYRO_ZOUT_H)? as f32 / 131.0;
+            acc_x += read_raw_data(&mut *self.i2c.lock().unwrap(), ADDR as u8, ACCEL_XOUT_H)?
+                as f32
+                / 16384.0;
+            acc_y += read_raw_data(&mut *self.i2c.lock().unwrap(), ADDR as u8, ACCEL_YOUT_H)?
+                as f32
+                / 16384.0;
+            acc_z += read_raw_data(&mut *self.i2c.lock().unwrap(), ADDR as u8, ACCEL_ZOUT_H)?
+                as f32
+                / 16384.0;
+            gyro_x += read_raw_data(&mut *self.i2c.lock().unwrap(), ADDR as u8, GYRO_XOUT_H)?
+                as f32
+                / 131.0;
+            gyro_y += read_raw_data(&mut *self.i2c.lock().unwrap(), ADDR as u8, GYRO_YOUT_H)?
+                as f32
+                / 131.0;
+            gyro_z += read_raw_data(&mut *self.i2c.lock().unwrap(), ADDR as u8, GYRO_ZOUT_H)?
+                as f32
+                / 131.0;
         }
 
         acc_x /= samples as f32;
@@ -406,3 +409,58 @@ impl MPU6050 {
         Ok((acc_x, acc_y, acc_z, gyro_x, gyro_y, gyro_z))
     }
 }
+
+/**
+The complementary filter behind `run`, fusing one reading per `step`.
+
+It takes the time since the previous reading instead of measuring it, so the
+angles only depend on the readings and the given durations.
+*/
+pub(super) struct Filter<I> {
+    i2c: Arc<Mutex<I>>,
+    roll: Arc<Mutex<f32>>,
+    pitch: Arc<Mutex<f32>>,
+    yaw: Arc<Mutex<f32>>,
+    acc_x_err: f32,
+    acc_y_err: f32,
+    gyro_x_err: f32,
+    gyro_y_err: f32,
+    gyro_z_err: f32,
+    gyro_angle_x: f32,
+    gyro_angle_y: f32,
+    last_yaw_rate: f32,
+}
+
+impl<I: Bus> Filter<I> {
+    /// Reads the sensor and integrates the rotation over `elapsed`.
+    pub(super) fn step(&mut self, elapsed: Duration) -> Result<()> {
+        let mut i2c = self.i2c.lock().unwrap();
+        let acc_x = read_raw_data(&mut *i2c, ADDR as u8, ACCEL_XOUT_H)? as f32 / 16384.0;
+        let acc_y = read_raw_data(&mut *i2c, ADDR as u8, ACCEL_YOUT_H)? as f32 / 16384.0;
+        let acc_z = read_raw_data(&mut *i2c, ADDR as u8, ACCEL_ZOUT_H)? as f32 / 16384.0;
+
+        let acc_angle_x =
+            (acc_y / (acc_x.powi(2) + acc_z.powi(2)).sqrt()).atan() * 180.0 / PI - self.acc_x_err;
+        let acc_angle_y = (-(acc_x / (acc_y.powi(2) + acc_z.powi(2)).sqrt()).atan() * 180.0 / PI)
+            - self.acc_y_err;
+
+        let gyro_x = read_raw_data(&mut *i2c, ADDR as u8, GYRO_XOUT_H)? as f32 / 131.0;
+        let gyro_y = read_raw_data(&mut *i2c, ADDR as u8, GYRO_YOUT_H)? as f32 / 131.0;
+        let gyro_z = read_raw_data(&mut *i2c, ADDR as u8, GYRO_ZOUT_H)? as f32 / 131.0;
+        drop(i2c);
+
+        let elapsed_time = elapsed.as_secs_f32();
+        self.gyro_angle_x += (gyro_x - self.gyro_x_err) * elapsed_time;
+        self.gyro_angle_y += (gyro_y - self.gyro_y_err) * elapsed_time;
+
+        let mut yaw = self.yaw.lock().unwrap();
+        *yaw += (gyro_z - self.gyro_z_err + self.last_yaw_rate) * 0.5 * elapsed_time;
+        self.last_yaw_rate = gyro_z - self.gyro_z_err;
+        *yaw %= 360.0;
+
+        *self.roll.lock().unwrap() = 0.98 * self.gyro_angle_x + 0.02 * acc_angle_x;
+        *self.pitch.lock().unwrap() = 0.98 * self.gyro_angle_y + 0.02 * acc_angle_y;
+
+        Ok(())
+    }
+}
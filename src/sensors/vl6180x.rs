@@ -1,66 +1,339 @@
 #![allow(dead_code)]
-use super::{read8, write8};
-use anyhow::Result;
+use super::{read16, read8, write16, write8};
+use embedded_hal::i2c::I2c as Bus;
 use rppal::i2c::I2c;
+use std::fmt;
+use std::time::{Duration, Instant};
 
-const ADDR: u16 = 0x29;
+pub(super) const ADDR: u16 = 0x29;
 
-const IDENTIFICATION_MODEL_ID: u16 = 0x000;
-const SYSTEM_CHANGE_ADDRESS: u16 = 0x212;
-const SYSTEM_HISTORY_CTRL: u16 = 0x012;
-const SYSTEM_INTERRUPT_CONFIG: u16 = 0x014;
-const SYSTEM_INTERRUPT_CLEAR: u16 = 0x015;
-const SYSTEM_FRESH_OUT_OF_RESET: u16 = 0x016;
+pub(super) const IDENTIFICATION_MODEL_ID: u16 = 0x000;
+pub(super) const SYSTEM_CHANGE_ADDRESS: u16 = 0x212;
+pub(super) const SYSTEM_HISTORY_CTRL: u16 = 0x012;
+pub(super) const SYSTEM_INTERRUPT_CONFIG: u16 = 0x014;
+pub(super) const SYSTEM_INTERRUPT_CLEAR: u16 = 0x015;
+pub(super) const SYSTEM_FRESH_OUT_OF_RESET: u16 = 0x016;
 
-const SYSRANGE_START: u16 = 0x018;
-const SYSRANGE_INTERMEASUREMENT_PERIOD: u16 = 0x01B;
-const SYSRANGE_PART_TO_PART_RANGE_OFFSET: u16 = 0x024;
+pub(super) const SYSRANGE_START: u16 = 0x018;
+pub(super) const SYSRANGE_INTERMEASUREMENT_PERIOD: u16 = 0x01B;
+pub(super) const SYSRANGE_PART_TO_PART_RANGE_OFFSET: u16 = 0x024;
 
-const RESULT_RANGE_STATUS: u16 = 0x04D;
-const RESULT_INTERRUPT_STATUS_GPIO: u16 = 0x04F;
-const RESULT_RANGE_VAL: u16 = 0x062;
-const RESULT_RANGE_HISTORY_BUFFER_0: i16 = 0x052;
+pub(super) const SYSALS_START: u16 = 0x038;
+pub(super) const SYSALS_INTERMEASUREMENT_PERIOD: u16 = 0x03E;
+pub(super) const SYSALS_ANALOGUE_GAIN: u16 = 0x03F;
+pub(super) const SYSALS_INTEGRATION_PERIOD: u16 = 0x040;
 
-pub struct VL6180X {
-    i2c: I2c,
+pub(super) const RESULT_RANGE_STATUS: u16 = 0x04D;
+pub(super) const RESULT_ALS_STATUS: u16 = 0x04E;
+pub(super) const RESULT_INTERRUPT_STATUS_GPIO: u16 = 0x04F;
+pub(super) const RESULT_ALS_VAL: u16 = 0x050;
+pub(super) const RESULT_RANGE_VAL: u16 = 0x062;
+pub(super) const RESULT_RANGE_HISTORY_BUFFER_0: i16 = 0x052;
+
+const MODEL_ID: u8 = 0xB4;
+
+/// What can go wrong talking to the sensor, on a bus failing with `E`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error<E = rppal::i2c::Error> {
+    /// The bus failed, like when nothing answers on the address.
+    Bus(E),
+    /// No result was ready before the timeout.
+    Timeout,
+    /// Something else answered on the address, with this model id.
+    WrongModel(u8),
+    /// The measurement isn't a distance, from `Measurement::valid_distance`.
+    Range(RangeStatus),
+    /// The continuous measurement period, in ms, isn't between 10 and 2550.
+    Period(i32),
+    /// The ALS integration period, in ms, isn't between 1 and 512.
+    IntegrationPeriod(u16),
+}
+
+impl<E: fmt::Display> fmt::Display for Error<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Bus(err) => write!(f, "I2C error: {}", err),
+            Error::Timeout => write!(f, "Timed out waiting for the VL6180X"),
+            Error::WrongModel(id) => write!(f, "Not a VL6180X, model id {:#04X}", id),
+            Error::Range(status) => write!(f, "Range error: {}", status),
+            Error::Period(period) => {
+                write!(f, "Period must be between 10 and 2550, not {}", period)
+            }
+            Error::IntegrationPeriod(period) => write!(
+                f,
+                "Integration period must be between 1 and 512, not {}",
+                period
+            ),
+        }
+    }
+}
+
+impl<E: std::error::Error + 'static> std::error::Error for Error<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Bus(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl<E> From<E> for Error<E> {
+    fn from(err: E) -> Self {
+        Error::Bus(err)
+    }
+}
+
+pub type Result<T, E = rppal::i2c::Error> = std::result::Result<T, Error<E>>;
+
+/// The error code of a measurement, from the high nibble of
+/// `RESULT_RANGE_STATUS`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RangeStatus {
+    Valid,
+    /// The VCSEL (the laser) failed its continuity test.
+    VcselContinuity,
+    /// The VCSEL watchdog test failed.
+    VcselWatchdogTest,
+    /// The VCSEL watchdog fired.
+    VcselWatchdog,
+    Pll1Lock,
+    Pll2Lock,
+    /// The early convergence estimate check failed: too little signal came
+    /// back, nothing is in range.
+    EarlyConvergenceEstimate,
+    /// The system didn't converge before the max convergence time: no target.
+    MaxConvergence,
+    /// The signal was below the ignore threshold: no target.
+    NoTargetIgnore,
+    /// Too much ambient light for the signal that came back.
+    MaxSignalToNoise,
+    RawRangingUnderflow,
+    RawRangingOverflow,
+    /// The target is closer than the sensor can range.
+    RangingUnderflow,
+    /// The target is further than the sensor can range.
+    RangingOverflow,
+    /// A code the datasheet doesn't use.
+    Unknown(u8),
+}
+
+impl RangeStatus {
+    fn from_register(value: u8) -> Self {
+        match value >> 4 {
+            0 => RangeStatus::Valid,
+            1 => RangeStatus::VcselContinuity,
+            2 => RangeStatus::VcselWatchdogTest,
+            3 => RangeStatus::VcselWatchdog,
+            4 => RangeStatus::Pll1Lock,
+            5 => RangeStatus::Pll2Lock,
+            6 => RangeStatus::EarlyConvergenceEstimate,
+            7 => RangeStatus::MaxConvergence,
+            8 => RangeStatus::NoTargetIgnore,
+            11 => RangeStatus::MaxSignalToNoise,
+            12 => RangeStatus::RawRangingUnderflow,
+            13 => RangeStatus::RawRangingOverflow,
+            14 => RangeStatus::RangingUnderflow,
+            15 => RangeStatus::RangingOverflow,
+            code => RangeStatus::Unknown(code),
+        }
+    }
+
+    /// Nothing came back close enough to range: for a wall sensor, no wall.
+    pub fn out_of_range(&self) -> bool {
+        matches!(
+            self,
+            RangeStatus::EarlyConvergenceEstimate
+                | RangeStatus::MaxConvergence
+                | RangeStatus::NoTargetIgnore
+                | RangeStatus::RawRangingOverflow
+                | RangeStatus::RangingOverflow
+        )
+    }
+
+    /// The measurement says nothing about what is in front of the sensor, it
+    /// should be taken again.
+    pub fn glitch(&self) -> bool {
+        *self != RangeStatus::Valid && !self.out_of_range()
+    }
+}
+
+impl fmt::Display for RangeStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RangeStatus::Valid => write!(f, "valid"),
+            RangeStatus::VcselContinuity => write!(f, "VCSEL continuity test failed"),
+            RangeStatus::VcselWatchdogTest => write!(f, "VCSEL watchdog test failed"),
+            RangeStatus::VcselWatchdog => write!(f, "VCSEL watchdog"),
+            RangeStatus::Pll1Lock => write!(f, "PLL1 lock failed"),
+            RangeStatus::Pll2Lock => write!(f, "PLL2 lock failed"),
+            RangeStatus::EarlyConvergenceEstimate => write!(f, "ECE check failed"),
+            RangeStatus::MaxConvergence => write!(f, "max convergence time"),
+            RangeStatus::NoTargetIgnore => write!(f, "no target"),
+            RangeStatus::MaxSignalToNoise => write!(f, "signal to noise too low"),
+            RangeStatus::RawRangingUnderflow => write!(f, "raw ranging underflow"),
+            RangeStatus::RawRangingOverflow => write!(f, "raw ranging overflow"),
+            RangeStatus::RangingUnderflow => write!(f, "ranging underflow"),
+            RangeStatus::RangingOverflow => write!(f, "ranging overflow"),
+            RangeStatus::Unknown(code) => write!(f, "unknown error code {}", code),
+        }
+    }
+}
+
+/// A range reading: the distance in mm, which only means something if the
+/// status is `Valid`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Measurement {
+    pub distance: u8,
+    pub status: RangeStatus,
+}
+
+impl Measurement {
+    pub fn valid(&self) -> bool {
+        self.status == RangeStatus::Valid
+    }
+
+    /// The distance, or an `Error::Range` if the measurement isn't valid.
+    pub fn valid_distance<E>(&self) -> Result<u8, E> {
+        if self.valid() {
+            Ok(self.distance)
+        } else {
+            Err(Error::Range(self.status))
+        }
+    }
+}
+
+impl fmt::Display for Measurement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.valid() {
+            write!(f, "{}", self.distance)
+        } else {
+            write!(f, "{} ({})", self.distance, self.status)
+        }
+    }
+}
+
+/// The analogue gain of the ambient light sensor. Higher gains read dimmer
+/// light, but saturate sooner.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlsGain {
+    X1,
+    X1_25,
+    X1_67,
+    X2_5,
+    X5,
+    X10,
+    X20,
+    X40,
+}
+
+impl AlsGain {
+    fn register(&self) -> u8 {
+        match self {
+            AlsGain::X1 => 0x06,
+            AlsGain::X1_25 => 0x05,
+            AlsGain::X1_67 => 0x04,
+            AlsGain::X2_5 => 0x03,
+            AlsGain::X5 => 0x02,
+            AlsGain::X10 => 0x01,
+            AlsGain::X20 => 0x00,
+            AlsGain::X40 => 0x07,
+        }
+    }
+
+    fn factor(&self) -> f32 {
+        match self {
+            AlsGain::X1 => 1.0,
+            AlsGain::X1_25 => 1.25,
+            AlsGain::X1_67 => 1.67,
+            AlsGain::X2_5 => 2.5,
+            AlsGain::X5 => 5.0,
+            AlsGain::X10 => 10.0,
+            AlsGain::X20 => 20.0,
+            AlsGain::X40 => 40.0,
+        }
+    }
+}
+
+// Lux per ALS count at gain 1 and 100 ms of integration, from the datasheet.
+const LUX_PER_COUNT: f32 = 0.32;
+
+pub struct VL6180X<I = I2c> {
+    i2c: I,
     addr: u16,
+    timeout: Duration,
+    // A single measurement started by `try_range` and not read yet.
+    measuring: bool,
+    als_gain: AlsGain,
+    // In ms.
+    als_integration_period: u16,
 }
 
-impl VL6180X {
-    pub fn new(bus: u8, addr: Option<u16>) -> Result<Self> {
-        let i2c = I2c::with_bus(bus)?;
+impl VL6180X<I2c> {
+    pub fn new(bus: u8, addr: Option<u16>) -> anyhow::Result<Self> {
+        Ok(Self::with_i2c(I2c::with_bus(bus)?, addr))
+    }
+}
+
+impl<I: Bus> VL6180X<I> {
+    /// A sensor on any bus, like `sensors::mock::MockI2c`. Nothing is sent
+    /// until `begin`.
+    pub fn with_i2c(i2c: I, addr: Option<u16>) -> Self {
         let addr = addr.unwrap_or(ADDR);
-        Ok(Self { i2c, addr })
+        Self {
+            i2c,
+            addr,
+            timeout: Duration::from_millis(100),
+            measuring: false,
+            als_gain: AlsGain::X1,
+            als_integration_period: 100,
+        }
     }
 
-    pub fn begin(&mut self) -> Result<()> {
-        self.i2c.set_slave_address(self.addr)?;
-        if let Err(_) = read8(&mut self.i2c, IDENTIFICATION_MODEL_ID) {
-            self.i2c.set_slave_address(ADDR)?;
-            self.change_addr(self.addr)?;
-            self.i2c.set_slave_address(self.addr)?;
+    /// How long `range` waits for a result before giving up, 100 ms by
+    /// default. In continuous mode it has to be longer than the period.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    pub fn begin(&mut self) -> Result<(), I::Error> {
+        // Still on the default address, so it moves from there. The driver
+        // only follows once the sensor took the new one.
+        if read8(&mut self.i2c, self.addr as u8, IDENTIFICATION_MODEL_ID).is_err() {
+            let target = self.addr & 0x7F;
+            write8(
+                &mut self.i2c,
+                ADDR as u8,
+                SYSTEM_CHANGE_ADDRESS,
+                target as u8,
+            )?;
+            self.addr = target;
         }
-        if read8(&mut self.i2c, IDENTIFICATION_MODEL_ID)? != 0xB4 {
-            return Err(anyhow::anyhow!(
-                "Could not connect to VL6180X on address: {}",
-                self.addr
-            ));
+        let model = read8(&mut self.i2c, self.addr as u8, IDENTIFICATION_MODEL_ID)?;
+        if model != MODEL_ID {
+            return Err(Error::WrongModel(model));
         }
 
         self.load_settings()?;
-        write8(&mut self.i2c, SYSTEM_FRESH_OUT_OF_RESET, 0x00)?;
+        write8(
+            &mut self.i2c,
+            self.addr as u8,
+            SYSTEM_FRESH_OUT_OF_RESET,
+            0x00,
+        )?;
 
         if self.continuous_mode_enabled()? {
             self.stop_range_continuous()?;
             std::thread::sleep(std::time::Duration::from_millis(10));
         }
 
-        write8(&mut self.i2c, SYSTEM_HISTORY_CTRL, 0x01)?;
+        write8(&mut self.i2c, self.addr as u8, SYSTEM_HISTORY_CTRL, 0x01)?;
+        self.set_als_gain(self.als_gain)?;
+        self.set_als_integration_period(self.als_integration_period)?;
 
         Ok(())
     }
 
-    pub fn range(&mut self) -> Result<u8> {
+    pub fn range(&mut self) -> Result<Measurement, I::Error> {
         if self.continuous_mode_enabled()? {
             self.read_range_continuous()
         } else {
@@ -68,102 +341,376 @@ impl VL6180X {
         }
     }
 
-    fn load_settings(&mut self) -> Result<()> {
-        write8(&mut self.i2c, 0x0207, 0x01)?;
-        write8(&mut self.i2c, 0x0208, 0x01)?;
-        write8(&mut self.i2c, 0x0096, 0x00)?;
-        write8(&mut self.i2c, 0x0097, 0xFD)?;
-        write8(&mut self.i2c, 0x00E3, 0x00)?;
-        write8(&mut self.i2c, 0x00E4, 0x04)?;
-        write8(&mut self.i2c, 0x00E5, 0x02)?;
-        write8(&mut self.i2c, 0x00E6, 0x01)?;
-        write8(&mut self.i2c, 0x00E7, 0x03)?;
-        write8(&mut self.i2c, 0x00F5, 0x02)?;
-        write8(&mut self.i2c, 0x00D9, 0x05)?;
-        write8(&mut self.i2c, 0x00DB, 0xCE)?;
-        write8(&mut self.i2c, 0x00DC, 0x03)?;
-        write8(&mut self.i2c, 0x00DD, 0xF8)?;
-        write8(&mut self.i2c, 0x009F, 0x00)?;
-        write8(&mut self.i2c, 0x00A3, 0x3C)?;
-        write8(&mut self.i2c, 0x00B7, 0x00)?;
-        write8(&mut self.i2c, 0x00BB, 0x3C)?;
-        write8(&mut self.i2c, 0x00B2, 0x09)?;
-        write8(&mut self.i2c, 0x00CA, 0x09)?;
-        write8(&mut self.i2c, 0x0198, 0x01)?;
-        write8(&mut self.i2c, 0x01B0, 0x17)?;
-        write8(&mut self.i2c, 0x01AD, 0x00)?;
-        write8(&mut self.i2c, 0x00FF, 0x05)?;
-        write8(&mut self.i2c, 0x0100, 0x05)?;
-        write8(&mut self.i2c, 0x0199, 0x05)?;
-        write8(&mut self.i2c, 0x01A6, 0x1B)?;
-        write8(&mut self.i2c, 0x01AC, 0x3E)?;
-        write8(&mut self.i2c, 0x01A7, 0x1F)?;
-        write8(&mut self.i2c, 0x0030, 0x00)?;
-
-        write8(&mut self.i2c, 0x0011, 0x10)?;
-        write8(&mut self.i2c, 0x010A, 0x30)?;
-        write8(&mut self.i2c, 0x003F, 0x46)?;
-        write8(&mut self.i2c, 0x0031, 0xFF)?;
-        write8(&mut self.i2c, 0x0040, 0x63)?;
-        write8(&mut self.i2c, 0x002E, 0x01)?;
-
-        write8(&mut self.i2c, 0x001B, 0x09)?;
-        write8(&mut self.i2c, 0x003E, 0x31)?;
-        write8(&mut self.i2c, 0x0014, 0x24)?;
+    fn load_settings(&mut self) -> Result<(), I::Error> {
+        write8(&mut self.i2c, self.addr as u8, 0x0207, 0x01)?;
+        write8(&mut self.i2c, self.addr as u8, 0x0208, 0x01)?;
+        write8(&mut self.i2c, self.addr as u8, 0x0096, 0x00)?;
+        write8(&mut self.i2c, self.addr as u8, 0x0097, 0xFD)?;
+        write8(&mut self.i2c, self.addr as u8, 0x00E3, 0x00)?;
+        write8(&mut self.i2c, self.addr as u8, 0x00E4, 0x04)?;
+        write8(&mut self.i2c, self.addr as u8, 0x00E5, 0x02)?;
+        write8(&mut self.i2c, self.addr as u8, 0x00E6, 0x01)?;
+        write8(&mut self.i2c, self.addr as u8, 0x00E7, 0x03)?;
+        write8(&mut self.i2c, self.addr as u8, 0x00F5, 0x02)?;
+        write8(&mut self.i2c, self.addr as u8, 0x00D9, 0x05)?;
+        write8(&mut self.i2c, self.addr as u8, 0x00DB, 0xCE)?;
+        write8(&mut self.i2c, self.addr as u8, 0x00DC, 0x03)?;
+        write8(&mut self.i2c, self.addr as u8, 0x00DD, 0xF8)?;
+        write8(&mut self.i2c, self.addr as u8, 0x009F, 0x00)?;
+        write8(&mut self.i2c, self.addr as u8, 0x00A3, 0x3C)?;
+        write8(&mut self.i2c, self.addr as u8, 0x00B7, 0x00)?;
+        write8(&mut self.i2c, self.addr as u8, 0x00BB, 0x3C)?;
+        write8(&mut self.i2c, self.addr as u8, 0x00B2, 0x09)?;
+        write8(&mut self.i2c, self.addr as u8, 0x00CA, 0x09)?;
+        write8(&mut self.i2c, self.addr as u8, 0x0198, 0x01)?;
+        write8(&mut self.i2c, self.addr as u8, 0x01B0, 0x17)?;
+        write8(&mut self.i2c, self.addr as u8, 0x01AD, 0x00)?;
+        write8(&mut self.i2c, self.addr as u8, 0x00FF, 0x05)?;
+        write8(&mut self.i2c, self.addr as u8, 0x0100, 0x05)?;
+        write8(&mut self.i2c, self.addr as u8, 0x0199, 0x05)?;
+        write8(&mut self.i2c, self.addr as u8, 0x01A6, 0x1B)?;
+        write8(&mut self.i2c, self.addr as u8, 0x01AC, 0x3E)?;
+        write8(&mut self.i2c, self.addr as u8, 0x01A7, 0x1F)?;
+        write8(&mut self.i2c, self.addr as u8, 0x0030, 0x00)?;
+
+        write8(&mut self.i2c, self.addr as u8, 0x0011, 0x10)?;
+        write8(&mut self.i2c, self.addr as u8, 0x010A, 0x30)?;
+        write8(&mut self.i2c, self.addr as u8, 0x003F, 0x46)?;
+        write8(&mut self.i2c, self.addr as u8, 0x0031, 0xFF)?;
+        write8(&mut self.i2c, self.addr as u8, 0x0040, 0x63)?;
+        write8(&mut self.i2c, self.addr as u8, 0x002E, 0x01)?;
+
+        write8(&mut self.i2c, self.addr as u8, 0x001B, 0x09)?;
+        write8(&mut self.i2c, self.addr as u8, 0x003E, 0x31)?;
+        write8(&mut self.i2c, self.addr as u8, 0x0014, 0x24)?;
 
         Ok(())
     }
 
-    pub fn change_addr(&mut self, addr: u16) -> Result<()> {
-        write8(&mut self.i2c, SYSTEM_CHANGE_ADDRESS, addr as u8 & 0x7F)?;
+    pub fn change_addr(&mut self, addr: u16) -> Result<(), I::Error> {
+        write8(
+            &mut self.i2c,
+            self.addr as u8,
+            SYSTEM_CHANGE_ADDRESS,
+            addr as u8 & 0x7F,
+        )?;
+        self.addr = addr & 0x7F;
         Ok(())
     }
 
-    pub fn start_range_continuous(&mut self, period: i32) -> Result<()> {
+    pub fn start_range_continuous(&mut self, period: i32) -> Result<(), I::Error> {
         if let 10..=2550 = period {
             let period_reg = period / 10 - 1;
             write8(
                 &mut self.i2c,
+                self.addr as u8,
                 SYSRANGE_INTERMEASUREMENT_PERIOD,
                 period_reg as u8,
             )?;
-            write8(&mut self.i2c, SYSRANGE_START, 0x03)?;
+            write8(&mut self.i2c, self.addr as u8, SYSRANGE_START, 0x03)?;
             Ok(())
         } else {
-            Err(anyhow::anyhow!("Period must be between 10 and 2550"))
+            Err(Error::Period(period))
         }
     }
 
-    pub fn stop_range_continuous(&mut self) -> Result<()> {
+    pub fn stop_range_continuous(&mut self) -> Result<(), I::Error> {
         if self.continuous_mode_enabled()? {
-            write8(&mut self.i2c, SYSRANGE_START, 0x01)?;
+            write8(&mut self.i2c, self.addr as u8, SYSRANGE_START, 0x01)?;
         }
         Ok(())
     }
 
-    fn continuous_mode_enabled(&mut self) -> Result<bool> {
-        Ok(read8(&mut self.i2c, SYSRANGE_START)? > 1 & 0x1)
+    fn continuous_mode_enabled(&mut self) -> Result<bool, I::Error> {
+        Ok(read8(&mut self.i2c, self.addr as u8, SYSRANGE_START)? & 0x02 != 0)
     }
 
-    pub fn offset(&mut self, offset: u8) -> Result<()> {
+    pub fn offset(&mut self, offset: u8) -> Result<(), I::Error> {
         write8(
             &mut self.i2c,
+            self.addr as u8,
             SYSRANGE_PART_TO_PART_RANGE_OFFSET,
             offset.to_le_bytes()[0],
         )?;
         Ok(())
     }
 
-    fn read_range_single(&mut self) -> Result<u8> {
-        while read8(&mut self.i2c, RESULT_RANGE_STATUS)? & 0x01 == 0 {}
-        write8(&mut self.i2c, SYSRANGE_START, 0x01)?;
-        Ok(self.read_range_continuous()?)
+    /// Like `range` without waiting: `None` if the result isn't ready yet.
+    /// Outside continuous mode the first call starts a measurement and a later
+    /// one reads it.
+    pub fn try_range(&mut self) -> Result<Option<Measurement>, I::Error> {
+        if self.range_ready()? {
+            return self.read_range().map(Some);
+        }
+        if !self.measuring && !self.continuous_mode_enabled()? && self.device_ready()? {
+            write8(&mut self.i2c, self.addr as u8, SYSRANGE_START, 0x01)?;
+            self.measuring = true;
+        }
+        Ok(None)
+    }
+
+    fn device_ready(&mut self) -> Result<bool, I::Error> {
+        Ok(read8(&mut self.i2c, self.addr as u8, RESULT_RANGE_STATUS)? & 0x01 != 0)
+    }
+
+    fn range_ready(&mut self) -> Result<bool, I::Error> {
+        Ok(read8(&mut self.i2c, self.addr as u8, RESULT_INTERRUPT_STATUS_GPIO)? & 0x04 != 0)
+    }
+
+    // Polls `ready` until it holds, or fails after `timeout`.
+    fn wait(
+        &mut self,
+        ready: fn(&mut Self) -> Result<bool, I::Error>,
+        timeout: Duration,
+    ) -> Result<(), I::Error> {
+        let start = Instant::now();
+        while !ready(self)? {
+            if start.elapsed() > timeout {
+                return Err(Error::Timeout);
+            }
+        }
+        Ok(())
+    }
+
+    fn read_range_single(&mut self) -> Result<Measurement, I::Error> {
+        if !self.measuring {
+            self.wait(Self::device_ready, self.timeout)?;
+            write8(&mut self.i2c, self.addr as u8, SYSRANGE_START, 0x01)?;
+        }
+        self.read_range_continuous()
+    }
+
+    fn read_range_continuous(&mut self) -> Result<Measurement, I::Error> {
+        self.wait(Self::range_ready, self.timeout)?;
+        self.read_range()
+    }
+
+    fn read_range(&mut self) -> Result<Measurement, I::Error> {
+        self.measuring = false;
+        let distance = read8(&mut self.i2c, self.addr as u8, RESULT_RANGE_VAL)?;
+        let status = read8(&mut self.i2c, self.addr as u8, RESULT_RANGE_STATUS)?;
+        write8(&mut self.i2c, self.addr as u8, SYSTEM_INTERRUPT_CLEAR, 0x05)?;
+        Ok(Measurement {
+            distance,
+            status: RangeStatus::from_register(status),
+        })
+    }
+
+    /// The ambient light in lux: a single reading, or the last continuous one
+    /// once a new one is ready. It waits for the integration period on top of
+    /// the timeout.
+    pub fn lux(&mut self) -> Result<f32, I::Error> {
+        let timeout = self.timeout + Duration::from_millis(self.als_integration_period as u64);
+        if !self.als_continuous_mode_enabled()? {
+            self.wait(Self::als_device_ready, timeout)?;
+            write8(&mut self.i2c, self.addr as u8, SYSALS_START, 0x01)?;
+        }
+        self.wait(Self::als_ready, timeout)?;
+        let count = read16(&mut self.i2c, self.addr as u8, RESULT_ALS_VAL)? as u16;
+        write8(&mut self.i2c, self.addr as u8, SYSTEM_INTERRUPT_CLEAR, 0x06)?;
+        Ok(
+            count as f32 * LUX_PER_COUNT / self.als_gain.factor() * 100.0
+                / self.als_integration_period as f32,
+        )
+    }
+
+    pub fn set_als_gain(&mut self, gain: AlsGain) -> Result<(), I::Error> {
+        write8(
+            &mut self.i2c,
+            self.addr as u8,
+            SYSALS_ANALOGUE_GAIN,
+            0x40 | gain.register(),
+        )?;
+        self.als_gain = gain;
+        Ok(())
+    }
+
+    /// How long, in ms, the ambient light sensor collects light for a
+    /// reading: longer is less noisy, 100 by default.
+    pub fn set_als_integration_period(&mut self, period: u16) -> Result<(), I::Error> {
+        if let 1..=512 = period {
+            write16(
+                &mut self.i2c,
+                self.addr as u8,
+                SYSALS_INTEGRATION_PERIOD,
+                period - 1,
+            )?;
+            self.als_integration_period = period;
+            Ok(())
+        } else {
+            Err(Error::IntegrationPeriod(period))
+        }
+    }
+
+    /// Starts reading the ambient light every `period` ms, which has to be
+    /// longer than the integration period.
+    pub fn start_als_continuous(&mut self, period: i32) -> Result<(), I::Error> {
+        if let 10..=2550 = period {
+            let period_reg = period / 10 - 1;
+            write8(
+                &mut self.i2c,
+                self.addr as u8,
+                SYSALS_INTERMEASUREMENT_PERIOD,
+                period_reg as u8,
+            )?;
+            write8(&mut self.i2c, self.addr as u8, SYSALS_START, 0x03)?;
+            Ok(())
+        } else {
+            Err(Error::Period(period))
+        }
+    }
+
+    pub fn stop_als_continuous(&mut self) -> Result<(), I::Error> {
+        if self.als_continuous_mode_enabled()? {
+            write8(&mut self.i2c, self.addr as u8, SYSALS_START, 0x01)?;
+        }
+        Ok(())
+    }
+
+    fn als_continuous_mode_enabled(&mut self) -> Result<bool, I::Error> {
+        Ok(read8(&mut self.i2c, self.addr as u8, SYSALS_START)? & 0x02 != 0)
+    }
+
+    fn als_device_ready(&mut self) -> Result<bool, I::Error> {
+        Ok(read8(&mut self.i2c, self.addr as u8, RESULT_ALS_STATUS)? & 0x01 != 0)
+    }
+
+    fn als_ready(&mut self) -> Result<bool, I::Error> {
+        let status = read8(&mut self.i2c, self.addr as u8, RESULT_INTERRUPT_STATUS_GPIO)?;
+        Ok((status >> 3) & 0x07 == 0x04)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sensors::mock::{tof_on_bus, MockI2c, Registers};
+
+    #[test]
+    fn failed_address_change_keeps_the_address() {
+        let mut tof = VL6180X::with_i2c(MockI2c::new(), Some(0x30));
+        assert!(matches!(tof.begin(), Err(Error::Bus(_))));
+        assert_eq!(tof.addr, 0x30);
+    }
+
+    #[test]
+    fn something_else_on_the_address_is_the_wrong_model() {
+        let bus = MockI2c::new();
+        let mut registers = Registers::new(2);
+        registers.set(IDENTIFICATION_MODEL_ID, 0xEE);
+        bus.add_device(ADDR as u8, registers);
+        let mut tof = VL6180X::with_i2c(bus, None);
+        assert_eq!(tof.begin(), Err(Error::WrongModel(0xEE)));
+    }
+
+    #[test]
+    fn range_times_out_on_a_slow_sensor() {
+        let (mut tof, sim) = tof_on_bus();
+        tof.set_timeout(Duration::from_millis(20));
+        sim.lock().unwrap().set_latency(u32::MAX);
+        sim.lock().unwrap().set_distance(Some(80));
+        assert_eq!(tof.range(), Err(Error::Timeout));
+
+        // The measurement still going on keeps the next one from starting.
+        assert_eq!(tof.range(), Err(Error::Timeout));
+        assert_eq!(tof.try_range(), Ok(None));
+    }
+
+    #[test]
+    fn range_status_decodes_every_code() {
+        use RangeStatus::*;
+        let statuses = [
+            (0, Valid, false, false),
+            (1, VcselContinuity, false, true),
+            (2, VcselWatchdogTest, false, true),
+            (3, VcselWatchdog, false, true),
+            (4, Pll1Lock, false, true),
+            (5, Pll2Lock, false, true),
+            (6, EarlyConvergenceEstimate, true, false),
+            (7, MaxConvergence, true, false),
+            (8, NoTargetIgnore, true, false),
+            (9, Unknown(9), false, true),
+            (10, Unknown(10), false, true),
+            (11, MaxSignalToNoise, false, true),
+            (12, RawRangingUnderflow, false, true),
+            (13, RawRangingOverflow, true, false),
+            (14, RangingUnderflow, false, true),
+            (15, RangingOverflow, true, false),
+        ];
+        for (code, status, out_of_range, glitch) in statuses {
+            // The low nibble is the device ready and error bits.
+            assert_eq!(RangeStatus::from_register(code << 4 | 0x01), status);
+            assert_eq!(status.out_of_range(), out_of_range, "{:?}", status);
+            assert_eq!(status.glitch(), glitch, "{:?}", status);
+        }
+    }
+
+    #[test]
+    fn lux_with_every_gain_and_period() {
+        let (mut tof, sim) = tof_on_bus();
+        sim.lock().unwrap().set_lux(50.0);
+        let gains = [
+            AlsGain::X1,
+            AlsGain::X1_25,
+            AlsGain::X1_67,
+            AlsGain::X2_5,
+            AlsGain::X5,
+            AlsGain::X10,
+            AlsGain::X20,
+            AlsGain::X40,
+        ];
+        for gain in gains {
+            for period in [1, 50, 100, 512] {
+                tof.set_als_gain(gain).unwrap();
+                tof.set_als_integration_period(period).unwrap();
+                // Off by at most half a count.
+                let tolerance = LUX_PER_COUNT / gain.factor() * 100.0 / period as f32 / 2.0;
+                let lux = tof.lux().unwrap();
+                assert!(
+                    (lux - 50.0).abs() <= tolerance + 0.001,
+                    "{} lux at {:?} and {} ms",
+                    lux,
+                    gain,
+                    period
+                );
+            }
+        }
+        assert_eq!(
+            tof.set_als_integration_period(0),
+            Err(Error::IntegrationPeriod(0))
+        );
+    }
+
+    #[test]
+    fn continuous_lux_follows_the_light() {
+        let (mut tof, sim) = tof_on_bus();
+        sim.lock().unwrap().set_latency(3);
+        sim.lock().unwrap().set_lux(100.0);
+        tof.start_als_continuous(200).unwrap();
+        assert!(sim.lock().unwrap().als_continuous());
+        // Half a count at gain 1 and 100 ms.
+        let tolerance = LUX_PER_COUNT / 2.0 + 0.001;
+        assert!((tof.lux().unwrap() - 100.0).abs() <= tolerance);
+        sim.lock().unwrap().set_lux(20.0);
+        assert!((tof.lux().unwrap() - 20.0).abs() <= tolerance);
+        tof.stop_als_continuous().unwrap();
+        assert!(!sim.lock().unwrap().als_continuous());
     }
 
-    fn read_range_continuous(&mut self) -> Result<u8> {
-        while read8(&mut self.i2c, RESULT_INTERRUPT_STATUS_GPIO)? & 0x04 == 0 {}
-        let range = read8(&mut self.i2c, RESULT_RANGE_VAL)?;
-        write8(&mut self.i2c, SYSTEM_INTERRUPT_CLEAR, 0x07)?;
-        Ok(range)
+    #[test]
+    fn continuous_mode_is_read_back() {
+        let (mut tof, sim) = tof_on_bus();
+        assert!(!tof.continuous_mode_enabled().unwrap());
+        tof.start_range_continuous(50).unwrap();
+        assert!(tof.continuous_mode_enabled().unwrap());
+        assert_eq!(
+            sim.lock()
+                .unwrap()
+                .register(SYSRANGE_INTERMEASUREMENT_PERIOD),
+            4
+        );
+        tof.stop_range_continuous().unwrap();
+        assert!(!tof.continuous_mode_enabled().unwrap());
+        assert_eq!(tof.start_range_continuous(5), Err(Error::Period(5)));
     }
 }
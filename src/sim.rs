@@ -0,0 +1,678 @@
+/*!
+A ground-truth maze and a simulator that drives the real [`Maze`] explorer
+through it, feeding it perfect sensor readings.
+
+# Field format
+
+A field is written the same way [`Maze::print_maze`] draws the map: one block
+per floor, starting with a `level <n>` line, where every tile is one character
+surrounded by its walls.
+
+```text
+level 0
++-+-+-+
+|S  B |
++ +-+ +
+|C|# R
++-+-+-+
+level 1
++-+-+-+-+
+ . . . H|
++ + + + +
+```
+
+- Tiles: `S` start, ` ` empty, `C` checkpoint, `B` blue, `#` (or `█`) black,
+  `R` ramp, `.` no tile, `O` an obstacle filling the tile and `o` one on its
+  top left quarter, which leaves room to get past.
+- Walls: `-` and `|`, a space for no wall, `+` at every corner.
+- Victims are drawn instead of the wall they are on: `H`, `S`, `U` and `r`,
+  `y`, `g` for the red, yellow and green ones. A victim on a wall between two
+  tiles faces the tile below or to the right of it.
+- A ramp leads to the tile straight ahead on the floor above or below, so that
+  tile has to be on the same column and row of its own floor, and has to be
+  open on the side opposite to it: a robot drives a ramp straight through.
+*/
+#![allow(dead_code)]
+use crate::map::{
+    self, Direction, Kind, Maze, Obstacle, Position, Quarter, Relocalization, Side, VictimKind,
+    DIRECTIONS, QUARTERS,
+};
+use crate::scoring::{self, Rules, Score};
+use anyhow::{anyhow, bail, Result};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+#[derive(Clone)]
+pub struct Tile {
+    pub kind: Kind,
+    pub links: HashMap<Direction, Position>,
+    pub victims: HashMap<Direction, VictimKind>,
+    pub obstacle: Option<Obstacle>,
+}
+
+impl Tile {
+    pub fn new(kind: Kind) -> Self {
+        Tile {
+            kind,
+            links: HashMap::new(),
+            victims: HashMap::new(),
+            obstacle: None,
+        }
+    }
+}
+
+fn tile_obstacle(c: char) -> Option<Obstacle> {
+    match c {
+        'O' => Some(Obstacle::new(&QUARTERS)),
+        'o' => Some(Obstacle::new(&[Quarter::UpLeft])),
+        _ => None,
+    }
+}
+
+/// The real layout of a maze.
+#[derive(Clone)]
+pub struct Field {
+    pub tiles: HashMap<Position, Tile>,
+    pub start: Position,
+}
+
+fn tile_kind(c: char) -> Option<Kind> {
+    match c {
+        'S' => Some(Kind::Start),
+        ' ' | 'O' | 'o' => Some(Kind::Empty),
+        'C' => Some(Kind::Checkpoint),
+        'B' => Some(Kind::Blue),
+        '#' | '█' => Some(Kind::Black),
+        'R' => Some(Kind::Ramp),
+        _ => None,
+    }
+}
+
+fn tile_symbol(tile: &Tile) -> char {
+    match tile.obstacle {
+        Some(obstacle) if obstacle == Obstacle::new(&QUARTERS) => return 'O',
+        Some(_) => return 'o',
+        None => (),
+    }
+    match tile.kind {
+        Kind::Start => 'S',
+        Kind::Checkpoint => 'C',
+        Kind::Blue => 'B',
+        Kind::Black => '#',
+        Kind::Ramp => 'R',
+        Kind::Empty | Kind::Unknown => ' ',
+    }
+}
+
+fn victim_kind(c: char) -> Option<VictimKind> {
+    match c {
+        'H' => Some(VictimKind::H),
+        'S' => Some(VictimKind::S),
+        'U' => Some(VictimKind::U),
+        'r' => Some(VictimKind::Red),
+        'y' => Some(VictimKind::Yellow),
+        'g' => Some(VictimKind::Green),
+        _ => None,
+    }
+}
+
+fn victim_symbol(kind: VictimKind) -> char {
+    match kind {
+        VictimKind::H => 'H',
+        VictimKind::S => 'S',
+        VictimKind::U => 'U',
+        VictimKind::Red => 'r',
+        VictimKind::Yellow => 'y',
+        VictimKind::Green => 'g',
+    }
+}
+
+enum Edge {
+    Open,
+    Wall,
+    Victim(VictimKind),
+}
+
+impl Field {
+    pub fn load(path: impl AsRef<Path>) -> Result<Field> {
+        Field::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn parse(text: &str) -> Result<Field> {
+        let mut levels: Vec<(i32, Vec<Vec<char>>)> = vec![];
+        for line in text.lines() {
+            if let Some(level) = line.strip_prefix("level ") {
+                levels.push((level.trim().parse()?, vec![]));
+            } else if let Some((_, rows)) = levels.last_mut() {
+                rows.push(line.chars().collect());
+            } else if !line.trim().is_empty() {
+                bail!("Expected a level line, got: {}", line);
+            }
+        }
+
+        let mut tiles = HashMap::new();
+        let mut edges = HashMap::new();
+        for (level, rows) in &levels {
+            let at = |row: usize, col: usize| rows.get(row).and_then(|r| r.get(col)).copied();
+            for row in (1..rows.len()).step_by(2) {
+                for col in (1..rows[row].len()).step_by(2) {
+                    let pos = ((col / 2) as i32, (row / 2) as i32, *level);
+                    let c = rows[row][col];
+                    if c == '.' {
+                        continue;
+                    }
+                    let kind =
+                        tile_kind(c).ok_or_else(|| anyhow!("Invalid tile '{}' at {:?}", c, pos))?;
+                    let mut tile = Tile::new(kind);
+                    tile.obstacle = tile_obstacle(c);
+                    tiles.insert(pos, tile);
+
+                    let sides = [
+                        (Direction::Up, at(row - 1, col)),
+                        (Direction::Down, at(row + 1, col)),
+                        (Direction::Left, at(row, col - 1)),
+                        (Direction::Right, at(row, col + 1)),
+                    ];
+                    for (direction, c) in sides {
+                        let edge = match c {
+                            None | Some(' ') => Edge::Open,
+                            Some('-') | Some('|') => Edge::Wall,
+                            Some(c) => Edge::Victim(victim_kind(c).ok_or_else(|| {
+                                anyhow!("Invalid wall '{}' at {:?} {:?}", c, pos, direction)
+                            })?),
+                        };
+                        edges.insert((pos, direction), edge);
+                    }
+                }
+            }
+        }
+
+        let positions: Vec<Position> = tiles.keys().copied().collect();
+        for pos in positions {
+            for direction in DIRECTIONS {
+                let next = direction.step(pos);
+                match edges.get(&(pos, direction)) {
+                    Some(Edge::Open) => {
+                        if tiles.contains_key(&next) {
+                            tiles.get_mut(&pos).unwrap().links.insert(direction, next);
+                        } else if tiles[&pos].kind == Kind::Ramp {
+                            for level in [pos.2 + 1, pos.2 - 1] {
+                                let exit = (next.0, next.1, level);
+                                if tiles.contains_key(&exit) {
+                                    tiles.get_mut(&pos).unwrap().links.insert(direction, exit);
+                                    tiles
+                                        .get_mut(&exit)
+                                        .unwrap()
+                                        .links
+                                        .insert(direction.back(), pos);
+                                    break;
+                                }
+                            }
+                        }
+                    }
+                    Some(Edge::Victim(kind)) => {
+                        let faces_other = matches!(direction, Direction::Down | Direction::Right)
+                            && tiles.contains_key(&next);
+                        if !faces_other {
+                            tiles
+                                .get_mut(&pos)
+                                .unwrap()
+                                .victims
+                                .insert(direction, *kind);
+                        }
+                    }
+                    Some(Edge::Wall) | None => (),
+                }
+            }
+        }
+
+        for (pos, tile) in &tiles {
+            if tile.kind != Kind::Ramp {
+                continue;
+            }
+            for direction in tile.links.keys() {
+                if !tile.links.contains_key(&direction.back()) {
+                    bail!(
+                        "The ramp at {:?} has no way out going {:?}",
+                        pos,
+                        direction.back()
+                    );
+                }
+            }
+        }
+
+        let mut starts = tiles.iter().filter(|(_, tile)| tile.kind == Kind::Start);
+        let start = match (starts.next(), starts.next()) {
+            (Some((pos, _)), None) => *pos,
+            _ => bail!("The field needs exactly one start tile"),
+        };
+        Ok(Field { tiles, start })
+    }
+
+    fn edge_symbol(&self, pos: Position, direction: Direction) -> char {
+        let next = direction.step(pos);
+        let tile = self.tiles.get(&pos);
+        let other = self.tiles.get(&next);
+        let victim = tile
+            .and_then(|tile| tile.victims.get(&direction))
+            .or_else(|| other.and_then(|tile| tile.victims.get(&direction.back())));
+        if let Some(victim) = victim {
+            return victim_symbol(*victim);
+        }
+        let linked = tile.is_some_and(|tile| tile.links.contains_key(&direction))
+            || other.is_some_and(|tile| tile.links.contains_key(&direction.back()));
+        if linked || (tile.is_none() && other.is_none()) {
+            ' '
+        } else if matches!(direction, Direction::Up | Direction::Down) {
+            '-'
+        } else {
+            '|'
+        }
+    }
+
+    /// Writes the field in the format [`Field::parse`] reads.
+    pub fn render(&self) -> String {
+        let max_x = self.tiles.keys().map(|pos| pos.0).max().unwrap_or(0);
+        let max_y = self.tiles.keys().map(|pos| pos.1).max().unwrap_or(0);
+        let mut levels: Vec<i32> = self.tiles.keys().map(|pos| pos.2).collect();
+        levels.sort();
+        levels.dedup();
+
+        let mut out = String::new();
+        for level in levels {
+            out.push_str(&format!("level {}\n", level));
+            for y in 0..=max_y {
+                let mut walls = String::new();
+                let mut tiles = String::new();
+                for x in 0..=max_x {
+                    let pos = (x, y, level);
+                    walls.push('+');
+                    walls.push(self.edge_symbol(pos, Direction::Up));
+                    tiles.push(self.edge_symbol(pos, Direction::Left));
+                    tiles.push(self.tiles.get(&pos).map_or('.', tile_symbol));
+                }
+                walls.push('+');
+                tiles.push(self.edge_symbol((max_x, y, level), Direction::Right));
+                out.push_str(&walls);
+                out.push('\n');
+                out.push_str(&tiles);
+                out.push('\n');
+            }
+            for x in 0..=max_x {
+                out.push('+');
+                out.push(self.edge_symbol((x, max_y, level), Direction::Down));
+            }
+            out.push_str("+\n");
+        }
+        out
+    }
+
+    /// Tiles a robot can stand on, starting from the start tile.
+    pub fn reachable(&self) -> HashSet<Position> {
+        let mut seen = HashSet::from([self.start]);
+        let mut queue = vec![self.start];
+        while let Some(pos) = queue.pop() {
+            for (direction, next) in &self.tiles[&pos].links {
+                let tile = &self.tiles[next];
+                let blocked = tile
+                    .obstacle
+                    .is_some_and(|obstacle| !obstacle.passable(*direction));
+                if tile.kind != Kind::Black && !blocked && seen.insert(*next) {
+                    queue.push(*next);
+                }
+            }
+        }
+        seen.retain(|pos| self.tiles[pos].kind != Kind::Ramp);
+        seen
+    }
+}
+
+pub struct Report {
+    pub steps: u32,
+    pub visited: usize,
+    pub reachable: usize,
+    pub victims_found: usize,
+    pub victims_total: usize,
+    pub returned_home: bool,
+    pub crashed: bool,
+    /// The maze couldn't tell where it was put back after a lack of
+    /// progress, so the run was stopped there.
+    pub lost: bool,
+    /// Walls and openings the maze got wrong.
+    pub wrong_sides: usize,
+    pub score: Score,
+}
+
+impl Report {
+    pub fn coverage(&self) -> f32 {
+        if self.reachable == 0 {
+            return 0.0;
+        }
+        self.visited as f32 / self.reachable as f32
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Steps: {}  Coverage: {}/{} ({:.0}%)  Victims: {}/{}  Home: {}  {}{}{}{}",
+            self.steps,
+            self.visited,
+            self.reachable,
+            self.coverage() * 100.0,
+            self.victims_found,
+            self.victims_total,
+            self.returned_home,
+            self.score,
+            if self.crashed { "  CRASHED" } else { "" },
+            if self.lost { "  LOST" } else { "" },
+            if self.wrong_sides > 0 {
+                format!("  WRONG SIDES: {}", self.wrong_sides)
+            } else {
+                String::new()
+            }
+        )
+    }
+}
+
+/// Moves a virtual robot through a [`Field`] following what a [`Maze`]
+/// decides, and tells the maze exactly what the robot would sense.
+pub struct Simulator {
+    field: Field,
+    pos: Position,
+    dir: Direction,
+    steps: u32,
+    visited: HashSet<Position>,
+    victims: HashSet<(Position, Direction)>,
+    checkpoint: Position,
+    checkpoint_dir: Direction,
+    put_back: Direction,
+    lack_of_progress: Option<u32>,
+}
+
+impl Simulator {
+    /// The robot starts on the start tile facing up, like a new [`Maze`].
+    pub fn new(field: Field) -> Self {
+        Simulator {
+            pos: field.start,
+            checkpoint: field.start,
+            checkpoint_dir: Direction::Up,
+            put_back: Direction::Up,
+            field,
+            dir: Direction::Up,
+            steps: 0,
+            visited: HashSet::new(),
+            victims: HashSet::new(),
+            lack_of_progress: None,
+        }
+    }
+
+    /// Calls a lack of progress after `steps` moves.
+    pub fn lack_of_progress_at(mut self, steps: u32) -> Self {
+        self.lack_of_progress = Some(steps);
+        self
+    }
+
+    /// How the robot is turned, from the heading it had there, when it is
+    /// put back on the checkpoint: `Up` the same way, the default, `Right` a
+    /// quarter to the right and so on.
+    pub fn put_back_turned(mut self, turn: Direction) -> Self {
+        self.put_back = turn;
+        self
+    }
+
+    fn relative(&self, direction: Direction) -> Direction {
+        if direction == self.dir {
+            Direction::Up
+        } else if direction == self.dir.right() {
+            Direction::Right
+        } else if direction == self.dir.left() {
+            Direction::Left
+        } else {
+            Direction::Down
+        }
+    }
+
+    fn absolute(&self, relative: Direction) -> Direction {
+        match relative {
+            Direction::Up => self.dir,
+            Direction::Right => self.dir.right(),
+            Direction::Left => self.dir.left(),
+            Direction::Down => self.dir.back(),
+        }
+    }
+
+    fn arrive(&mut self, maze: &mut Maze) {
+        self.visited.insert(self.pos);
+        let tile = self.field.tiles[&self.pos].clone();
+        let open = |direction: Direction| tile.links.contains_key(&direction);
+        maze.robot_scan(
+            open(self.dir),
+            open(self.dir.right()),
+            open(self.dir.left()),
+            open(self.dir.back()),
+        );
+        match tile.kind {
+            Kind::Blue => maze.add_blue(),
+            Kind::Checkpoint => {
+                self.checkpoint = self.pos;
+                self.checkpoint_dir = self.dir;
+                maze.add_checkpoint();
+            }
+            _ => (),
+        }
+        for face in DIRECTIONS {
+            let Some(obstacle) = tile
+                .links
+                .get(&face)
+                .and_then(|next| self.field.tiles[next].obstacle)
+            else {
+                continue;
+            };
+            // The scan above opened the side, so the maze has the tile.
+            maze.add_obstacle(self.relative(face), obstacle.seen_facing(self.dir))
+                .expect("The maze has no tile for the obstacle");
+        }
+        for (face, kind) in &tile.victims {
+            let side = self.relative(*face);
+            if maze.add_victim(*kind, side).is_some() {
+                maze.serve_victim(side);
+                self.victims.insert((self.pos, *face));
+            }
+        }
+    }
+
+    /// Where a tile of the maze is on the field. The maze starts at the
+    /// origin, wherever the start tile is on the field.
+    fn field_pos(&self, (x, y, level): Position) -> Position {
+        let (sx, sy, slevel) = self.field.start;
+        (x + sx, y + sy, level + slevel)
+    }
+
+    fn maze_pos(&self, (x, y, level): Position) -> Position {
+        let (sx, sy, slevel) = self.field.start;
+        (x - sx, y - sy, level - slevel)
+    }
+
+    /// Whether the maze marked a side as wall or open when it is the other
+    /// way round on the field.
+    fn wrong_side(&self, tile: &map::Tile, direction: Direction) -> bool {
+        let Some(real) = self.field.tiles.get(&self.field_pos(tile.pos)) else {
+            return false;
+        };
+        match tile.side(direction) {
+            Side::Wall => real.links.contains_key(&direction),
+            Side::Open => !real.links.contains_key(&direction),
+            Side::Unknown => false,
+        }
+    }
+
+    /// Puts the robot back on the last checkpoint after a lack of progress,
+    /// turned as set by [`Simulator::put_back_turned`], and lets the maze
+    /// work out where it is. Returns whether it could.
+    fn relocate(&mut self, maze: &mut Maze) -> bool {
+        maze.lack_of_progress();
+        self.pos = self.checkpoint;
+        self.dir = self.checkpoint_dir;
+        self.dir = self.absolute(self.put_back);
+        let tile = &self.field.tiles[&self.pos];
+        let open = |direction: Direction| tile.links.contains_key(&direction);
+        let result = maze.relocalize(
+            open(self.dir),
+            open(self.dir.right()),
+            open(self.dir.left()),
+            open(self.dir.back()),
+        );
+        if !matches!(result, Relocalization::Found(..)) {
+            return false;
+        }
+        self.arrive(maze);
+        true
+    }
+
+    /// Runs the maze until it has nothing left to do, or for `max_steps`
+    /// moves at most.
+    pub fn run(&mut self, maze: &mut Maze, max_steps: u32) -> Report {
+        let mut crashed = false;
+        let mut lost = false;
+        self.arrive(maze);
+        while self.steps < max_steps {
+            if self.lack_of_progress == Some(self.steps) {
+                self.lack_of_progress = None;
+                if !self.relocate(maze) {
+                    lost = true;
+                    break;
+                }
+            }
+            let Some(relative) = maze.move_one() else {
+                break;
+            };
+            self.steps += 1;
+            self.dir = self.absolute(relative);
+
+            let Some(next) = self.field.tiles[&self.pos].links.get(&self.dir).copied() else {
+                crashed = true;
+                break;
+            };
+            if let Some(obstacle) = self.field.tiles[&next].obstacle {
+                if !obstacle.passable(self.dir) {
+                    crashed = true;
+                    break;
+                }
+            }
+            match self.field.tiles[&next].kind {
+                Kind::Black => {
+                    maze.add_black();
+                    continue;
+                }
+                Kind::Ramp => {
+                    // A field built by hand rather than parsed can have a
+                    // ramp that ends in a wall.
+                    let Some(exit) = self.field.tiles[&next].links.get(&self.dir).copied() else {
+                        crashed = true;
+                        break;
+                    };
+                    // The maze drives a ramp it knows in one go, but has to
+                    // be told about one it doesn't.
+                    if maze.kind(self.maze_pos(next)) != Some(Kind::Ramp) {
+                        maze.add_ramp(exit.2 > next.2);
+                    }
+                    self.pos = exit;
+                }
+                _ => self.pos = next,
+            }
+            self.arrive(maze);
+        }
+
+        let total = self
+            .field
+            .tiles
+            .values()
+            .map(|tile| tile.victims.len())
+            .sum();
+        Report {
+            steps: self.steps,
+            visited: self.visited.len(),
+            reachable: self.field.reachable().len(),
+            victims_found: self.victims.len(),
+            victims_total: total,
+            returned_home: self.pos == self.field.start,
+            crashed,
+            lost,
+            wrong_sides: maze
+                .tiles()
+                .map(|tile| {
+                    DIRECTIONS
+                        .into_iter()
+                        .filter(|direction| self.wrong_side(&tile, *direction))
+                        .count()
+                })
+                .sum(),
+            score: scoring::score(maze.run_log(), &Rules::default()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator::{self, Settings};
+
+    fn assert_explored(field: Field, name: &str) {
+        let mut maze = Maze::new();
+        maze.set_strategy(map::strategy(name).unwrap());
+        let report = Simulator::new(field).run(&mut maze, 1000);
+        assert_eq!(report.visited, report.reachable, "{}: {}", name, report);
+        assert!(report.returned_home, "{}: {}", name, report);
+        assert!(!report.crashed, "{}: {}", name, report);
+        assert!(!report.lost, "{}: {}", name, report);
+        assert_eq!(report.wrong_sides, 0, "{}: {}", name, report);
+    }
+
+    #[test]
+    fn every_strategy_explores_the_example() {
+        let field = Field::parse(include_str!("../mazes/example.txt")).unwrap();
+        for name in map::STRATEGIES {
+            assert_explored(field.clone(), name);
+        }
+    }
+
+    #[test]
+    fn every_strategy_explores_generated_fields() {
+        for seed in 0..10 {
+            let field = generator::generate(&Settings::default(), seed).unwrap();
+            for name in map::STRATEGIES {
+                assert_explored(field.clone(), name);
+            }
+        }
+    }
+
+    #[test]
+    fn ramps_have_to_lead_somewhere() {
+        let text = "level 0\n+-+-+\n|S R|\n+-+-+\n";
+        assert!(Field::parse(text).is_err());
+
+        // Open on the far side, with no tile there on either floor.
+        let text = "level 0\n+-+-+\n|S R \n+-+-+\nlevel 1\n+-+-+\n|. .|\n+-+-+\n";
+        assert!(Field::parse(text).is_err());
+    }
+
+    #[test]
+    fn a_ramp_into_a_wall_is_a_crash() {
+        let mut field = Field::parse(include_str!("../mazes/example.txt")).unwrap();
+        for tile in field.tiles.values_mut() {
+            if tile.kind == Kind::Ramp {
+                tile.links.remove(&Direction::Right);
+            }
+        }
+        for name in map::STRATEGIES {
+            let mut maze = Maze::new();
+            maze.set_strategy(map::strategy(name).unwrap());
+            let report = Simulator::new(field.clone()).run(&mut maze, 1000);
+            assert!(report.crashed, "{}: {}", name, report);
+        }
+    }
+}